@@ -3,133 +3,297 @@ use chrono::offset::Utc;
 
 use walkdir::WalkDir;
 use handlebars::{Handlebars};
+use csv;
 
 use config::*;
+use error::{GarError, Result};
 
+use std::io;
 use std::io::Read;
 use std::fs::File;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use models::event::Event;
-use models::archive::{Archive, ArchiveBuilder};
-use models::constraint::Constraint;
+use models::event::{Event, FEATURES};
+use models::archive::{Archive, ArchiveBuilder, FetchSettings, FetchStatus, FetchSummary};
+use models::expr::Expr;
+use models::verify;
+use models::date_range;
+use models::date_range::{DateArgs, DateRange};
+use models::cache;
+use models::cache::{CachedArchive, EvictionOrder, Policy};
+use models::index::ArchiveIndex;
+use models::compact;
+use models::export::SqliteExport;
+use models::index::stamp_of;
+use models::reader;
+use lock::DataLock;
+use progress::{Progress, ProgressMode};
 
 /// Print the current version of GAR
 pub fn version() -> () {
     println!("gar version {}", env!("CARGO_PKG_VERSION"));
 }
 
-/// Given a date in the format of "YYYY-m-d-h", parse the string into
-/// a DateTime<UTC> object
-fn parse_archive_date(date: String) -> Option<DateTime<Utc>> {
-    let vals: Vec<&str> = date.split("-").collect::<Vec<&str>>();
+/// Make an archive object for the hour `date`, which can then be used to fetch the data off the
+/// internets (or `source`, see `config::archive_url`).
+fn archive_at(date: DateTime<Utc>, source: &str) -> Result<Archive> {
+    let year  = date.year();
+    let month = date.month();
+    let day   = date.day();
+    let hour  = date.hour();
+
+    ArchiveBuilder::new()
+        .year(year)
+        .month(month)
+        .day(day)
+        .hour(hour)
+        .source(source)
+        .finalize()
+}
 
-    if vals.len() < 4 {
-        println!("Error {:?}: You need to provide a date in the format of dd-mm-yyyy", date);
-        return None;
+/// Same as fetch, but we're fetching one or more date ranges (see `DateArgs::to_ranges`), both
+/// ends included. Up to `jobs` archives are downloaded at once (see `config::fetch_jobs` for the
+/// default). `progress` says how to report progress on stderr (see `progress_mode`).
+///
+/// Archives that could not be fetched are listed in the returned summary; the error case is for
+/// things that stop the whole run, such as bad arguments or configuration.
+pub fn fetch_rng(dates: DateArgs, jobs: Option<String>, source: Option<String>,
+                 progress: Option<String>) -> Result<FetchSummary> {
+    if dates.is_empty() {
+        return Err(GarError::Parse("You need to say which dates to fetch (--from/--to, --day, \
+                                    --month, --last or --range)".into()));
     }
 
-    #[inline]
-    fn try_int_parse(s: Option<&str>) -> Option<u32> {
-        match s {
-            Some(v) => match v.parse::<u32>() {
-                Ok(iv) => Some(iv),
-                Err(..) => None,
-            },
-            None => None,
-        }
+    let now: DateTime<Utc> = Utc::now();
+    let hours: Vec<DateTime<Utc>> = date_range::hours_of(&dates.to_ranges_at(now)?, now)?;
+
+    let jobs: usize = match jobs {
+        Some(j) => parse_jobs(&j)?,
+        None => fetch_jobs()?,
+    };
+
+    let progress: ProgressMode = progress_mode(progress)?;
+    let source: String = archive_url(source)?;
+    let settings: FetchSettings = fetch_settings()?;
+    let archives: Vec<Archive> = hours
+        .into_iter()
+        .map(|d| archive_at(d, &source))
+        .collect::<Result<Vec<Archive>>>()?;
+
+    let _lock: DataLock = DataLock::acquire()?;
+
+    let summary: FetchSummary = fetch_all(archives, jobs, progress, settings);
+    print_fetch_summary(&summary);
+
+    if auto_prune()? {
+        prune_locked(None, false)?;
     }
 
-    let mut it = vals.into_iter();
+    Ok(summary)
+}
 
-    Some(Utc.ymd(
-        try_int_parse(it.next()).unwrap() as i32,
-        try_int_parse(it.next()).unwrap(),
-        try_int_parse(it.next()).unwrap())
-       .and_hms(
-        try_int_parse(it.next()).unwrap(),
-        0, 0))
+/// The value of a `--progress` flag; no progress when there is none
+fn progress_mode(p: Option<String>) -> Result<ProgressMode> {
+    match p {
+        Some(p) => ProgressMode::parse(&p),
+        None => Ok(ProgressMode::Off),
+    }
 }
 
-/// Given a date in string format, try to make it into an archive object, which can then be used to
-/// fetch the data off the internets.
-fn parse_archive(date: String) -> Option<Archive> {
-    let date = match parse_archive_date(date) {
-        Some(v) => v,
-        None => return None,
-    };
+/// How long the printing thread of `fetch_all` and `run_query` waits for workers before updating
+/// the progress report anyway
+const PROGRESS_TICK: Duration = Duration::from_millis(100);
 
-    let year  = date.year();
-    let month = date.month();
-    let day   = date.day();
-    let hour  = date.hour();
+/// The value of a `--jobs` flag
+fn parse_jobs(j: &str) -> Result<usize> {
+    match j.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(GarError::Parse(format!("jobs needs to be a positive number, not {:?}", j))),
+    }
+}
 
-    let a: Archive =
-        ArchiveBuilder::new()
-            .year(year)
-            .month(month)
-            .day(day)
-            .hour(hour)
-            .finalize();
-    Some(a)
-}
-
-/// Same as fetch, but we're fetching a date range
-pub fn fetch_rng(from: Option<String>, to: Option<String>) -> () {
-    fn match_and_parse(o: Option<String>) -> Option<DateTime<Utc>> {
-        match o {
-            Some(v) => parse_archive_date(v),
-            None => return None,
-        }
+/// Why `fetch_all` failed an archive that no worker reported on
+const FETCH_WORKER_CRASHED: &str = "fetch worker crashed";
+
+/// Fetch the given archives with a pool of `jobs` worker threads. Workers only report back what
+/// happened (and how many bytes they downloaded); printing is left to this thread so that the
+/// status lines, and the progress report, do not interleave.
+fn fetch_all(archives: Vec<Archive>, jobs: usize, progress: ProgressMode,
+             settings: FetchSettings) -> FetchSummary {
+    let mut progress = Progress::new("fetch", progress, archives.len());
+    /* What no worker reported on by the end was lost to a crashed worker */
+    let mut unreported: BTreeSet<String> = archives.iter().map(|a| a.name().clone()).collect();
+    let queue = Arc::new(Mutex::new(archives.into_iter()));
+    let (tx, rx) = mpsc::channel::<(String, FetchStatus, usize)>();
+    let mut workers = Vec::new();
+
+    for _ in 0..jobs {
+        let queue = queue.clone();
+        let tx = tx.clone();
+
+        workers.push(thread::spawn(move || loop {
+            /* Hold the lock only long enough to take the next archive */
+            let next: Option<Archive> = match queue.lock() {
+                Ok(mut q) => q.next(),
+                /* Another worker panicked while taking its next archive */
+                Err(..) => break,
+            };
+            let mut archive = match next {
+                Some(a) => a,
+                None => break,
+            };
+            let status = archive.fetch(&settings);
+            if tx.send((archive.name().clone(), status, archive.data().len())).is_err() {
+                break;
+            }
+        }));
+    }
+
+    /* Only the workers hold senders now, so the loop below ends once they are all done */
+    drop(tx);
+
+    let mut summary = FetchSummary::default();
+
+    loop {
+        let (name, status, bytes) = match rx.recv_timeout(PROGRESS_TICK) {
+            Ok(fetched) => fetched,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                progress.tick();
+                continue;
+            },
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+
+        progress.clear();
+        print_fetch_status(&name, &status);
+        summary.record(&name, &status);
+        unreported.remove(&name);
+
+        progress.files_done += 1;
+        progress.bytes += bytes as u64;
+        progress.tick();
     }
 
-    let fr = match_and_parse(from);
-    let to = match_and_parse(to);
+    progress.finish();
 
-    if fr.is_none() || to.is_none() {
-        println!("You need to supply both from, and to option flags");
-        return;
+    /* A worker that panicked fails the archives it (or, through the lock, the others) left
+     * undone, rather than this thread */
+    for w in workers {
+        let _ = w.join();
     }
 
-    let mut fr_v = fr.unwrap();
-    let to_v = to.unwrap();
+    for name in unreported {
+        ::print_red(format!("Could not fetch {}: {}\n", name, FETCH_WORKER_CRASHED).as_ref());
+        summary.failed.push((name, FETCH_WORKER_CRASHED.into()));
+    }
+
+    summary
+}
 
-    if fr_v > to_v {
-        println!("You need to supply valid date ranges");
-        println!("From is after To in this case");
+fn print_fetch_status(name: &str, status: &FetchStatus) {
+    match *status {
+        FetchStatus::Fetched => ::print_green(format!("{} ok\n", name).as_ref()),
+        FetchStatus::Cached => ::print_yellow(format!("Data {} exists in cache - skip\n", name).as_ref()),
+        FetchStatus::NotFound => ::print_magenta(format!("No such info found on server ({})\n", name).as_ref()),
+        FetchStatus::Failed(ref e) => ::print_red(format!("Could not fetch {}: {}\n", name, e).as_ref()),
     }
+}
 
-    let one_hour = Duration::minutes(60);
-    let mut date_strings: Vec<String> = Vec::new();
+fn print_fetch_summary(summary: &FetchSummary) {
+    println!("{} fetched, {} skipped, {} failed",
+             summary.succeeded.len(), summary.skipped.len(), summary.failed.len());
 
-    while fr_v != to_v {
-        /* And now generate the dates within the range by incrementing by hour */
-        let s: String = format!("{}", fr_v.format("%Y-%m-%d-%H"));
-        date_strings.push(s);
-        fr_v = fr_v + one_hour;
+    for (name, reason) in &summary.failed {
+        ::print_red(format!("    {}: {}\n", name, reason).as_ref());
     }
+}
 
-    for date in date_strings {
-        fetch(date);
+/// Argument provided should be a single hour, such as YYYY-mm-dd-h (see `date_range::parse_span`)
+pub fn fetch(s: String, source: Option<String>) -> Result<FetchSummary> {
+    let mut archive = archive_at(date_range::parse_hour(&s)?, &archive_url(source)?)?;
+    let settings: FetchSettings = fetch_settings()?;
+    let _lock: DataLock = DataLock::acquire()?;
+
+    let status = archive.fetch(&settings);
+    print_fetch_status(archive.name(), &status);
+
+    if auto_prune()? {
+        prune_locked(None, false)?;
     }
+
+    let mut summary = FetchSummary::default();
+    summary.record(archive.name(), &status);
+    Ok(summary)
 }
 
-/// Argument provided should be in the form dd-mm-YYYY
-pub fn fetch(s: String) -> () {
-    let mut archive = match parse_archive(s) {
-        Some(a) => a,
-        None => panic!("Could not fetch archive"),
-    };
-    archive.fetch();
+/// Remove archives from the data folder until it meets the `max_size` and `max_age` limits of the
+/// configuration. `order` ("access" or "date") overrides the `prune_by` key. With `dry_run`,
+/// only print what would be removed. Returns false if some archive could not be removed.
+pub fn prune(order: Option<String>, dry_run: bool) -> Result<bool> {
+    let _lock: DataLock = DataLock::acquire()?;
+
+    prune_locked(order, dry_run)
 }
 
-pub fn ls_data() -> () {
-    let dpath: PathBuf = data_path();
-    let paths = match fs::read_dir(dpath) {
-        Ok(ps) => ps,
-        Err(e) => panic!("Problem with data directory {}", e),
-    };
+/// `prune`, for callers that already hold the data folder lock
+fn prune_locked(order: Option<String>, dry_run: bool) -> Result<bool> {
+    let policy: Policy = cache_policy()?;
+    let order: EvictionOrder = order.map_or_else(prune_order, |o| EvictionOrder::parse(&o))?;
+
+    if policy.is_empty() {
+        println!("No max_size or max_age set in the configuration - nothing to prune");
+        return Ok(true);
+    }
+
+    let cached: Vec<CachedArchive> = get_data_file_paths()?
+        .into_iter()
+        .filter_map(|p| {
+            let date = date_from_path(&p)?;
+            let meta = fs::metadata(&p).ok()?;
+            let accessed = meta.accessed().or_else(|_| meta.modified()).ok()?;
+            Some(CachedArchive { path: p, size: meta.len(), accessed, date })
+        })
+        .collect();
+
+    let evict: Vec<CachedArchive> = cache::plan(cached, &policy, order, SystemTime::now());
+    let mut freed: u64 = 0;
+    let mut ok: bool = true;
+
+    for a in evict {
+        let name: &str = a.path.to_str().unwrap_or("[uncapable of unwraping]");
+
+        if dry_run {
+            println!("    would remove {} [{}]", name, a.size);
+            freed += a.size;
+            continue;
+        }
+
+        match fs::remove_file(&a.path) {
+            Ok(..) => {
+                remove_derived(&a.path);
+                ::print_yellow(format!("    removed {} [{}]\n", name, a.size).as_ref());
+                freed += a.size;
+            },
+            Err(e) => {
+                ::print_red(format!("    could not remove {}: {}\n", name, e).as_ref());
+                ok = false;
+            },
+        }
+    }
+
+    println!("{} {} bytes", if dry_run { "Would free" } else { "Freed" }, freed);
+    Ok(ok)
+}
+
+pub fn ls_data() -> Result<()> {
+    let dpath: PathBuf = data_path()?;
+    let paths = fs::read_dir(dpath)?;
 
     for p in paths {
         /* p is Option<Result<DirEntry>> */
@@ -150,174 +314,874 @@ pub fn ls_data() -> () {
             Err(..) => continue,
         }
     }
+
+    Ok(())
+}
+
+/// Check every archive in the data directory: decompress it fully (which checks its gzip
+/// trailer), and count the events in it. Bad archives are moved to the quarantine directory when
+/// `quarantine` is set, and downloaded again (from `source`) when `repair` is set. Returns true
+/// when no bad archive is left behind.
+pub fn verify(quarantine: bool, repair: bool, source: Option<String>) -> Result<bool> {
+    let source: String = archive_url(source)?;
+    let settings: FetchSettings = fetch_settings()?;
+
+    /* Only needed when we are going to move files around */
+    let _lock: Option<DataLock> = if quarantine || repair {
+        Some(DataLock::acquire()?)
+    }
+    else {
+        None
+    };
+
+    let paths: Vec<PathBuf> = get_data_file_paths()?;
+    let total: usize = paths.len();
+    let mut bad: Vec<PathBuf> = Vec::new();
+
+    for p in paths {
+        let v = verify::verify(&p);
+        let name: String = p.to_str().unwrap_or("[uncapable of unwraping]").into();
+
+        match v.problem {
+            Some(ref problem) => {
+                ::print_red(format!("    {} BAD: {}\n", name, problem).as_ref());
+                bad.push(p.clone());
+            },
+            None if v.unparseable > 0 => {
+                ::print_yellow(format!("    {} ok, {} events, {} unparseable lines\n",
+                                       name, v.events, v.unparseable).as_ref());
+            },
+            None => {
+                ::print_green(format!("    {} ok, {} events\n", name, v.events).as_ref());
+            },
+        }
+    }
+
+    println!("{} archives checked, {} bad", total, bad.len());
+
+    let mut unresolved: usize = 0;
+
+    for p in bad {
+        remove_derived(&p);
+
+        let filename: String = match p.file_name().and_then(|f| f.to_str()) {
+            Some(f) => f.into(),
+            None => { unresolved += 1; continue },
+        };
+
+        if quarantine {
+            let mut dest: PathBuf = quarantine_path()?;
+            let moved = fs::create_dir_all(&dest).and_then(|_| {
+                dest.push(&filename);
+                fs::rename(&p, &dest)
+            });
+
+            match moved {
+                Ok(..) => ::print_yellow(format!("Moved {} to {}\n", filename,
+                                                 dest.to_str().unwrap_or("")).as_ref()),
+                Err(e) => {
+                    ::print_red(format!("Could not quarantine {}: {}\n", filename, e).as_ref());
+                    unresolved += 1;
+                    continue;
+                },
+            }
+        }
+        else if !repair {
+            unresolved += 1;
+            continue;
+        }
+
+        /* Without quarantine, the bad archive stays until a good one is renamed over it, so that
+         * a failed download leaves at least what there was */
+        if repair && !refetch(&filename, &source, &settings) {
+            unresolved += 1;
+        }
+    }
+
+    Ok(unresolved == 0)
+}
+
+/// Download an archive again, given its file name in the data directory, and store it whether
+/// caching is on or not, replacing whatever is there only once the download succeeded.
+fn refetch(filename: &str, source: &str, settings: &FetchSettings) -> bool {
+    /* because YYYY-mm-dd-h.json.gz */
+    let date: String = filename.split('.').next().unwrap_or("").into();
+
+    let mut archive: Archive = match date_range::parse_hour(&date).and_then(|d| archive_at(d, source)) {
+        Ok(a) => a,
+        Err(..) => return false,
+    };
+
+    let status = archive.download(settings);
+    print_fetch_status(archive.name(), &status);
+
+    match status {
+        FetchStatus::Fetched => match archive.store() {
+            Ok(..) => true,
+            Err(e) => {
+                ::print_red(format!("Could not store {}: {}\n", filename, e).as_ref());
+                false
+            },
+        },
+        _ => false,
+    }
+}
+
+/// Index every archive of the data folder that has no up to date index (every archive, with
+/// `force`), so that queries can skip the archives that can't match (see `models::index`).
+/// Indexes of archives that are gone are removed. Returns false if some archive could not be
+/// indexed.
+pub fn index(force: bool) -> Result<bool> {
+    let paths: Vec<PathBuf> = get_data_file_paths()?;
+    let mut indexed: usize = 0;
+    let mut fresh: usize = 0;
+    let mut failed: usize = 0;
+
+    for p in &paths {
+        let name: &str = p.to_str().unwrap_or("[uncapable of unwraping]");
+
+        if !force && ArchiveIndex::load_fresh(p).is_some() {
+            fresh += 1;
+            continue;
+        }
+
+        match ArchiveIndex::build(p).and_then(|idx| idx.store(p).map(|_| idx)) {
+            Ok(idx) => {
+                ::print_green(format!("    {} indexed, {} events\n", name, idx.events).as_ref());
+                indexed += 1;
+            },
+            Err(e) => {
+                ::print_red(format!("    {} could not be indexed: {}\n", name, e).as_ref());
+                ArchiveIndex::remove(p);
+                failed += 1;
+            },
+        }
+    }
+
+    let removed: usize = remove_orphans(index_path()?, &paths, index_file_path);
+
+    println!("{} indexed, {} up to date, {} failed, {} stale indexes removed",
+             indexed, fresh, failed, removed);
+    Ok(failed == 0)
+}
+
+/// Convert every archive of the data folder that has no up to date compacted version (every
+/// archive, with `force`) into the binary format of `models::compact`, which queries then read
+/// instead of the json. Compacted versions of archives that are gone are removed. Returns false if
+/// some archive could not be converted.
+pub fn compact(force: bool) -> Result<bool> {
+    let paths: Vec<PathBuf> = get_data_file_paths()?;
+    let mut converted: usize = 0;
+    let mut fresh: usize = 0;
+    let mut failed: usize = 0;
+
+    for p in &paths {
+        let name: &str = p.to_str().unwrap_or("[uncapable of unwraping]");
+
+        if !force && compact::is_fresh(p) {
+            fresh += 1;
+            continue;
+        }
+
+        match compact::write(p) {
+            Ok(events) => {
+                let size: u64 = compact_file_path(p).and_then(|c| Ok(fs::metadata(c)?.len())).unwrap_or(0);
+                ::print_green(format!("    {} compacted, {} events [{}]\n", name, events, size).as_ref());
+                converted += 1;
+            },
+            Err(e) => {
+                ::print_red(format!("    {} could not be compacted: {}\n", name, e).as_ref());
+                compact::remove(p);
+                failed += 1;
+            },
+        }
+    }
+
+    let removed: usize = remove_orphans(compact_path()?, &paths, compact_file_path);
+
+    println!("{} compacted, {} up to date, {} failed, {} stale files removed",
+             converted, fresh, failed, removed);
+    Ok(failed == 0)
+}
+
+/// Remove every file in `dir` that isn't the `derived` file (say, `index_file_path`) of one of
+/// `archives`: what is left from archives that are gone, or from interrupted writes. Returns how
+/// many files were removed.
+fn remove_orphans(dir: PathBuf, archives: &[PathBuf], derived: fn(&Path) -> Result<PathBuf>) -> usize {
+    let keep: BTreeSet<PathBuf> = archives.iter().filter_map(|p| derived(p).ok()).collect();
+
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(..) => return 0,
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| !keep.contains(&e.path()) && fs::remove_file(e.path()).is_ok())
+        .count()
+}
+
+/// Remove what was made out of the archive at `p` (its index and compacted version), once the
+/// archive itself is gone or known to be bad
+fn remove_derived(p: &Path) {
+    ArchiveIndex::remove(p);
+    compact::remove(p);
 }
 
 /// Print the standard paths that the app uses.
-pub fn show_paths() -> () {
-    let v = vec![config_path(), data_path(), config_file_path()];
+pub fn show_paths() -> Result<()> {
+    let v = vec![config_path()?, data_path()?, index_path()?, compact_path()?, config_file_path()?];
 
     v.into_iter()
-     .map(|e| ::print_green(format!("  {}\n", e.to_str().unwrap()).as_ref()))
+     .map(|e| ::print_green(format!("  {}\n", e.to_str().unwrap_or("[uncapable of unwraping]")).as_ref()))
      .for_each(drop);
+
+    Ok(())
 }
 
 /// TODO: I'm not sure if this is supported anymore?
 /// gar --select <attribute>+ --where <feature>+ --date YYYY-mm-dd-hh
 pub fn find_date(selects: Option<String>, wheres: Option<String>,
-                 date: String, template: Option<String>) -> () {
-    let d2 = date.clone();
-    find(Some(date), Some(d2), selects, wheres, template);
+                 date: String, template: Option<String>) -> Result<()> {
+    let dates = DateArgs {
+        from: Some(date.clone()),
+        to: Some(date),
+        ..DateArgs::default()
+    };
+    let opts = QueryOptions {
+        selects,
+        wheres,
+        template,
+        ..QueryOptions::default()
+    };
+    find(dates, opts)
 }
 
-/// Given a select, and where clause, match and find against those.
-/// gar --select <feature>+ --from <date> --to <date> --where <constraints>+
-///   where <date> is YYYY-mm-dd-hh
-///     and <constraints>+ is for example, language:Rust, name:potato
-pub fn find(from: Option<String>, to: Option<String>,
-            _selects: Option<String>, wheres: Option<String>,
-            template: Option<String>) -> () {
-    // TODO:
-    // let features: Vec<String> = match selects {
-    //     Some(s) => s.split(",").map(|e| e.to_string().clone()).collect(),
-    //     None => vec![],
-    // };
-
-    let constraints: Vec<String> = match wheres {
-        Some(s) => s.split(",").map(|e| e.to_string().clone()).collect(),
-        None => vec![],
-    };
+/// Everything about a query besides its dates
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Comma separated fields to print (see `event::FEATURES`), as delimited columns
+    pub selects: Option<String>,
+    pub wheres: Option<String>,
+    /// Path to a handlebars template to render each result with
+    pub template: Option<String>,
+    /// Stream archives from `source` instead of reading them from the data folder
+    pub remote: bool,
+    pub source: Option<String>,
+    /// How many archives to go through at once; one when not given
+    pub jobs: Option<String>,
+    /// With more than one job, print results in chronological order rather than as they come
+    pub ordered: bool,
+    /// Files, directories, or `-` for standard input, to query instead of the data folder
+    pub inputs: Vec<String>,
+    /// How to report progress on stderr: "text", "json" or "off" (the default)
+    pub progress: Option<String>,
+    /// How to print the selected fields: "csv" (the default) or "tsv"
+    pub format: Option<String>,
+}
 
-    let vcon: Vec<Constraint> = constraints
-        .into_iter()
-        .map(|e| { let mut tmp = e.split(":");
-                   Constraint { label: tmp.next().unwrap().to_string(),
-                                value: tmp.next().unwrap().to_string(), }})
-        .collect();
+/// How many lines of output query workers may get ahead of the printing thread
+const QUERY_BACKLOG: usize = 1024;
 
-    let chosen_paths_from_dates: Vec<PathBuf> = choose_files_from_dates(from, to);
+/// The error of a query one of whose workers panicked
+const WORKER_CRASHED: &str = "query worker crashed";
 
-    // TODO: This might need to be broken down to other functions later on
+/// Where the events of one archive of a query come from
+enum QuerySource {
+    /// An archive of the data folder
+    File(PathBuf),
+    /// An archive of the server, and how to download it
+    Remote(Archive, FetchSettings),
+    /// A file given with `--input`; when the flag is set, its events are filtered by date
+    Input(PathBuf, bool),
+    /// Events piped in, filtered by date
+    Stdin,
+}
 
-    if let Some(template) = template {
-        // Try to find the template
-        let template_path: PathBuf = PathBuf::from(template);
-        let mut temp_contents: String = String::new();
-        let mut f: File = match File::open(template_path) {
-            Ok(f) => f,
-            Err(e) => panic!("Could not load template: {}", e),
-        };
+/// What query workers report back, for the archive at the given index
+enum Found {
+    /// Output for one matching event
+    Match(usize, String),
+    /// Something to tell the user, that doesn't stop the query
+    Warning(String),
+    /// Nothing more will come for that archive
+    Done(usize),
+    /// The query itself is broken (say, a bad template): stop everything
+    Failed(GarError),
+}
 
-        f.read_to_string(&mut temp_contents).unwrap();
+/// How the events that match a query are printed
+enum Output {
+    /// Everything about the event, as `{:#?}` shows it
+    Debug,
+    Template(Handlebars<'static>),
+    /// One line per event with the given fields, separated by the given delimiter
+    Columns(Vec<String>, u8),
+}
 
-        let mut handlebars = Handlebars::new();
+/// The constraints and output of a query, shared by its workers
+struct Matcher {
+    filter: Expr,
+    output: Output,
+    /// The dates of the query, for sources that aren't known to be within them
+    ranges: Vec<DateRange>,
+    /// True when some constraint looks into payloads, so that they must be decoded before the
+    /// constraints are checked
+    payloads: bool,
+    /// How many events the workers have gone through, for the progress report
+    seen: AtomicU64,
+}
 
-        match handlebars.register_template_string("provided_template", temp_contents) {
-            Ok(..) => {},
-            Err(e) => panic!("Problem registering template: {}", e),
+impl Matcher {
+    /// True if `r` happened within the dates of the query. Events with no date are left out,
+    /// unless there are no dates to speak of.
+    fn in_dates(&self, r: &Event) -> bool {
+        match r.created_at() {
+            Some(d) => date_range::any_contains_time(&self.ranges, d),
+            None => self.ranges.is_empty(),
         }
+    }
 
-        for pth in chosen_paths_from_dates {
-            for r in Event::from_path(pth) {
-                if r.satisfies_constraints(&vcon) {
-                    let btree_json = r.to_btree_with_all_features();
-                    match handlebars.render("provided_template", &btree_json) {
-                        Ok(v) => print!("{}", v),
-                        Err(..) => continue,
-                    }
+    /// What to print for `r`, if it matches. Its payload is only decoded when the constraints or
+    /// the output need it: templates and columns don't show payloads, but the default output does.
+    fn render(&self, r: &mut Event) -> Result<Option<String>> {
+        if self.payloads { r.decode_payload() }
+        if !self.filter.matches(r) { return Ok(None) }
 
+        match self.output {
+            Output::Debug => {
+                r.decode_payload();
+                Ok(Some(format!("{:#?}\n", r)))
+            },
+            Output::Template(ref handlebars) => {
+                let btree_json = r.to_btree_with_all_features();
+                match handlebars.render("provided_template", &btree_json) {
+                    Ok(v) => Ok(Some(v)),
+                    Err(e) => Err(GarError::Template(e.to_string())),
                 }
-            }
+            },
+            Output::Columns(ref columns, delimiter) => {
+                let values: BTreeMap<String, String> = r.to_btree_with_features_of(columns);
+                let row: Vec<String> = columns
+                    .iter()
+                    .map(|c| values.get(c).cloned().unwrap_or_default())
+                    .collect();
+                Ok(Some(delimited_line(&row, delimiter)?))
+            },
         }
     }
+}
+
+/// Given a select, and where clause, match and find against those.
+/// gar --select <feature>+ --from <date> --to <date> --where <clause>
+///   where <date> is YYYY-mm-dd-hh (or anything else `DateArgs` accepts)
+///     and <clause> is for example, language:Rust,name:potato OR NOT type:fork
+/// With `remote`, archives are streamed from `source` instead of read from the data folder; with
+/// `inputs`, the given files are read instead (see `input_sources`). With `jobs`, that many
+/// archives are decompressed, parsed and filtered at once. With `progress`, how far along the
+/// query is gets reported on stderr.
+pub fn find(dates: DateArgs, opts: QueryOptions) -> Result<()> {
+    let filter: Expr = parse_where(opts.wheres)?;
+
+    let ranges: Vec<DateRange> = dates.to_ranges()?;
+
+    let jobs: usize = match opts.jobs {
+        Some(ref j) => parse_jobs(j)?,
+        None => 1,
+    };
+
+    let progress: ProgressMode = progress_mode(opts.progress)?;
+
+    let output: Output = match (opts.template, opts.selects, opts.format) {
+        (Some(..), Some(..), _) | (Some(..), _, Some(..)) =>
+            return Err(GarError::Query("--template can't be used with --select or --format".into())),
+        (Some(template), None, None) => Output::Template(load_template(template)?),
+        (None, None, None) => Output::Debug,
+        (None, selects, format) => Output::Columns(parse_selects(selects)?, delimiter_of(format)?),
+    };
+
+    if opts.remote && !opts.inputs.is_empty() {
+        return Err(GarError::Query("--remote and --input can't be used together".into()));
+    }
+
+    let sources: Vec<QuerySource> = if !opts.inputs.is_empty() {
+        input_sources(&opts.inputs, &ranges)?
+    }
+    else if opts.remote {
+        remote_sources(&ranges, &archive_url(opts.source)?, fetch_settings()?)?
+    }
     else {
-        for pth in chosen_paths_from_dates {
-            for r in Event::from_path(pth) {
-                /* r are the repos that are created when parsing a single gz file */
-
-                /* This part should have a few logical branches depending on what the user wants to do.
-                 * maybe default can be the pretty printing using the formatter bellow.
-                 */
-                if r.satisfies_constraints(&vcon) {
-                    println!("{:#?}", r);
-                }
-            }
+        choose_files_from_dates(&ranges)?
+            .into_iter()
+            .map(QuerySource::File)
+            .collect()
+    };
+
+    if let Output::Columns(ref columns, delimiter) = output {
+        print!("{}", delimited_line(columns, delimiter)?);
+    }
+
+    let payloads: bool = filter.needs_payload();
+    let matcher = Matcher { filter, output, ranges, payloads, seen: AtomicU64::new(0) };
+    run_query(sources, matcher, jobs, opts.ordered, progress)
+}
+
+/// The fields of a `--select`, in the order given; every field when there is none
+fn parse_selects(selects: Option<String>) -> Result<Vec<String>> {
+    let selects: String = match selects {
+        Some(s) => s,
+        None => return Ok(FEATURES.iter().map(|f| f.to_string()).collect()),
+    };
+
+    selects
+        .split(',')
+        .map(|f| f.trim())
+        .map(|f| if FEATURES.contains(&f) {
+            Ok(f.to_string())
         }
+        else {
+            Err(GarError::Query(format!("can't select {:?}; fields are {}", f, FEATURES.join(", "))))
+        })
+        .collect()
+}
+
+/// The delimiter of a `--format`: "csv" (the default) or "tsv"
+fn delimiter_of(format: Option<String>) -> Result<u8> {
+    match format.as_ref().map(|f| f.as_ref()) {
+        None | Some("csv") => Ok(b','),
+        Some("tsv") => Ok(b'\t'),
+        Some(f) => Err(GarError::Query(format!("format can be csv or tsv, not {:?}", f))),
     }
 }
 
-/// This will look into the ~/.config/gar/data folder, and match the
-/// filenames against the given dates. If the match is successful,
-/// then the path to that archive is returned.
-fn choose_files_from_dates(from: Option<String>, to: Option<String>) -> Vec<PathBuf> {
-    let v: Vec<PathBuf> = get_data_file_paths();
-
-    #[inline]
-    fn date_from_path(p: &PathBuf) -> DateTime<Utc> {
-        let dt: DateTime<Utc> = Utc::now();
-        let filename = p.file_name();
-
-        match filename {
-            Some(v) => {
-                let date = v.to_str()
-                            .unwrap()
-                            .split(".") /* because YYYY-mm-dd-h.json.gz */
-                            .nth(0)
-                            .unwrap()
-                            .to_string();
-
-                match parse_archive_date(date) {
-                    Some(date) => date,
-                    None => panic!("ruh roh"),
+/// `fields` as one line of csv (or tsv, or whatever `delimiter` makes it), quoted where needed
+fn delimited_line(fields: &[String], delimiter: u8) -> Result<String> {
+    let mut w = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+
+    w.write_record(fields).map_err(io::Error::from)?;
+    let line: Vec<u8> = w.into_inner().map_err(|e| e.into_error())?;
+
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// The `--where` clause, such as `language:Rust,type:push` (see `Expr`); no clause matches
+/// everything
+fn parse_where(wheres: Option<String>) -> Result<Expr> {
+    Expr::parse(&wheres.unwrap_or_default())
+}
+
+/// Go through `sources` with a pool of `jobs` worker threads, printing what matches. Like
+/// `fetch_all`, workers only send their output back, and this thread does all the printing.
+/// When `ordered`, output for an archive is held back until every archive before it is done, so
+/// results come out in the order of `sources`; otherwise they are printed as they come.
+fn run_query(sources: Vec<QuerySource>, matcher: Matcher, jobs: usize, ordered: bool,
+             progress: ProgressMode) -> Result<()> {
+    let mut progress = Progress::new("query", progress, sources.len());
+    progress.events = Some(0);
+    progress.matches = Some(0);
+    /* `bytes_read` counts for the whole process */
+    let bytes_before: u64 = reader::bytes_read();
+
+    let queue = Arc::new(Mutex::new(sources.into_iter().enumerate()));
+    let matcher = Arc::new(matcher);
+    /* Bounded, so that workers wait for the printing rather than pile results up in memory */
+    let (tx, rx) = mpsc::sync_channel::<Found>(QUERY_BACKLOG);
+    let mut workers = Vec::new();
+
+    for _ in 0..jobs {
+        let queue = queue.clone();
+        let matcher = matcher.clone();
+        let tx = tx.clone();
+
+        workers.push(thread::spawn(move || loop {
+            let next: Option<(usize, QuerySource)> = match queue.lock() {
+                Ok(mut q) => q.next(),
+                /* Another worker panicked while taking its next archive */
+                Err(..) => {
+                    let _ = tx.send(Found::Failed(GarError::Query(WORKER_CRASHED.into())));
+                    break;
+                },
+            };
+            let (ix, source) = match next {
+                Some(n) => n,
+                None => break,
+            };
+            if !scan(ix, source, &matcher, &tx) {
+                break;
+            }
+        }));
+    }
+
+    drop(tx);
+
+    /* Ordered mode: the archive whose output is printed straight away, output held back for
+     * later archives, and later archives that are already done */
+    let mut current: usize = 0;
+    let mut held: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let mut done: BTreeSet<usize> = BTreeSet::new();
+    let mut failure: Option<GarError> = None;
+
+    loop {
+        let found: Option<Found> = match rx.recv_timeout(PROGRESS_TICK) {
+            Ok(found) => Some(found),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+
+        match found {
+            Some(Found::Match(ix, out)) => {
+                *progress.matches.get_or_insert(0) += 1;
+                if !ordered || ix == current {
+                    progress.clear();
+                    print!("{}", out);
                 }
+                else {
+                    held.entry(ix).or_default().push(out);
+                }
+            },
+            Some(Found::Warning(msg)) => {
+                progress.clear();
+                ::print_red(format!("{}\n", msg).as_ref());
+            },
+            Some(Found::Done(ix)) => {
+                progress.files_done += 1;
+                done.insert(ix);
+                while done.remove(&current) {
+                    current += 1;
+                    for out in held.remove(&current).unwrap_or_default() {
+                        progress.clear();
+                        print!("{}", out);
+                    }
+                }
+            },
+            Some(Found::Failed(e)) => {
+                failure = Some(e);
+                break;
             },
-            None => dt
+            None => {},
         }
+
+        progress.events = Some(matcher.seen.load(Ordering::Relaxed));
+        progress.bytes = reader::bytes_read() - bytes_before;
+        progress.tick();
     }
 
-    if from.is_some() && to.is_some() {
-        let from_date: DateTime<Utc> = parse_archive_date(from.unwrap()).unwrap();
-        let to_date: DateTime<Utc> = parse_archive_date(to.unwrap()).unwrap();
+    progress.finish();
 
-        v.into_iter()
-         .filter(|e|{
-            let dfp = date_from_path(e);
-            dfp >= from_date && dfp <= to_date })
-         .collect()
+    /* Any worker still going fails its next send once this is gone, and stops */
+    drop(rx);
 
+    /* A worker that panicked (in a template helper, say) fails the query rather than this thread */
+    for w in workers {
+        if w.join().is_err() && failure.is_none() {
+            failure = Some(GarError::Query(WORKER_CRASHED.into()));
+        }
     }
-    else if from.is_some() && to.is_none() {
-        let from_date: DateTime<Utc> = parse_archive_date(from.unwrap()).unwrap();
 
-        v.into_iter()
-         .filter(|e| date_from_path(e) >= from_date)
-         .collect()
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
     }
-    else if from.is_none() && to.is_some() {
-        let to_date: DateTime<Utc> = parse_archive_date(to.unwrap()).unwrap();
+}
 
-        v.into_iter()
-         .filter(|e| date_from_path(e) <= to_date)
-         .collect()
+/// Send what matches in one archive back to the printing thread. An archive that turns out to be
+/// unreadable (see `gar verify`) is reported and left behind, rather than stopping the query.
+/// Returns false when the query is over, and the worker should stop.
+fn scan(ix: usize, source: QuerySource, matcher: &Matcher, tx: &mpsc::SyncSender<Found>) -> bool {
+    let mut by_date: bool = false;
+
+    /* Archives without an up to date index get one, built from the events as they go by */
+    let mut indexing: Option<(PathBuf, ArchiveIndex)> = None;
+
+    /* For archives that can't be read at all: tell why, and go on with the next one */
+    let skip = |msg: String| tx.send(Found::Warning(msg)).is_ok() && tx.send(Found::Done(ix)).is_ok();
+
+    let events: Box<dyn Iterator<Item = Result<Event>>> = match source {
+        QuerySource::File(p) => {
+            match ArchiveIndex::load_fresh(&p) {
+                Some(idx) => if !idx.may_match(&matcher.filter) {
+                    return tx.send(Found::Done(ix)).is_ok();
+                },
+                None => indexing = ArchiveIndex::for_archive(&p).ok().map(|idx| (p.clone(), idx)),
+            }
+            Box::new(Event::iter_path(p))
+        },
+        QuerySource::Remote(archive, settings) => match archive.open(&settings) {
+            /* The body goes straight into the decoder, so only a buffer's worth is held at once */
+            Ok(Some(body)) => Box::new(Event::iter_reader(body, &archive.url())),
+            Ok(None) => return skip(format!("No such info found on server ({})", archive.name())),
+            Err(e) => return skip(format!("Could not fetch {}: {}", archive.name(), e)),
+        },
+        QuerySource::Input(p, filter) => {
+            by_date = filter;
+            Box::new(Event::iter_json_path(p))
+        },
+        QuerySource::Stdin => {
+            by_date = true;
+            Box::new(Event::iter_reader(io::stdin(), "standard input"))
+        },
+    };
+
+    for r in events {
+        let mut r: Event = match r {
+            Ok(r) => r,
+            Err(e) => {
+                indexing = None;
+                if tx.send(Found::Warning(format!("Skipping the rest of an archive: {}", e))).is_err() {
+                    return false;
+                }
+                break;
+            },
+        };
+
+        matcher.seen.fetch_add(1, Ordering::Relaxed);
+
+        if let Some((_, ref mut idx)) = indexing {
+            idx.add(&r);
+        }
+
+        if by_date && !matcher.in_dates(&r) {
+            continue;
+        }
+
+        let sent = match matcher.render(&mut r) {
+            Ok(Some(out)) => tx.send(Found::Match(ix, out)),
+            Ok(None) => continue,
+            Err(e) => {
+                let _ = tx.send(Found::Failed(e));
+                return false;
+            },
+        };
+
+        if sent.is_err() {
+            return false;
+        }
+    }
+
+    /* Not being able to write an index only makes the next query slower */
+    if let Some((p, idx)) = indexing {
+        let _ = idx.store(&p);
+    }
+
+    tx.send(Found::Done(ix)).is_ok()
+}
+
+/// Export the events of the archives of the data folder within `dates` (all of them when there
+/// are no dates) that satisfy `wheres`, to the SQLite database at `out` (see `SqliteExport`).
+/// Archives already exported as they are, with the same `wheres`, are skipped; so running the
+/// same export again after a fetch only adds the new hours. Returns false if some archive could
+/// not be exported.
+pub fn export_sqlite(dates: DateArgs, wheres: Option<String>, out: String) -> Result<bool> {
+    let filter: String = wheres.clone().unwrap_or_default();
+    let clause: Expr = parse_where(wheres)?;
+
+    let paths: Vec<PathBuf> = if dates.is_empty() {
+        get_data_file_paths()?
     }
     else {
-        /* Both none - match all */
-        v.into_iter()
+        choose_files_from_dates(&dates.to_ranges()?)?
+    };
+
+    let mut db: SqliteExport = SqliteExport::open(Path::new(&out))?;
+    let mut exported: usize = 0;
+    let mut fresh: usize = 0;
+    let mut failed: usize = 0;
+
+    for p in &paths {
+        let name: String = match p.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.into(),
+            None => continue,
+        };
+        let stamp: (u64, u64) = match stamp_of(p) {
+            Ok(stamp) => stamp,
+            Err(e) => {
+                ::print_red(format!("    {} could not be exported: {}\n", name, e).as_ref());
+                failed += 1;
+                continue;
+            },
+        };
+
+        if db.is_up_to_date(&name, stamp, &filter)? {
+            fresh += 1;
+            continue;
+        }
+
+        /* Payloads are decoded anyway, since they get exported too */
+        let events = Event::iter_path(p.clone()).filter_map(|e| {
+            let mut e: Event = match e {
+                Ok(e) => e,
+                Err(err) => return Some(Err(err)),
+            };
+            e.decode_payload();
+
+            if clause.matches(&e) { Some(Ok(e)) } else { None }
+        });
+
+        match db.export(&name, stamp, &filter, events) {
+            Ok(events) => {
+                ::print_green(format!("    {} exported, {} events\n", name, events).as_ref());
+                exported += 1;
+            },
+            Err(e) => {
+                ::print_red(format!("    {} could not be exported: {}\n", name, e).as_ref());
+                failed += 1;
+            },
+        }
+    }
+
+    println!("{} exported, {} up to date, {} failed", exported, fresh, failed);
+    Ok(failed == 0)
+}
+
+/// Load and compile the handlebars template at `path`, under the name "provided_template"
+fn load_template(path: String) -> Result<Handlebars<'static>> {
+    let template_path: PathBuf = PathBuf::from(path);
+    let mut temp_contents: String = String::new();
+    let mut f: File = File::open(&template_path).map_err(|e| {
+        GarError::Template(format!("could not load {}: {}",
+                                   template_path.to_str().unwrap_or(""), e))
+    })?;
+
+    f.read_to_string(&mut temp_contents)?;
+
+    let mut handlebars = Handlebars::new();
+
+    if let Err(e) = handlebars.register_template_string("provided_template", temp_contents) {
+        return Err(GarError::Template(format!("problem registering template: {}", e)));
+    }
+
+    Ok(handlebars)
+}
+
+/// One archive per hour of `ranges`, to be downloaded from `source` and queried in memory.
+/// Nothing is written to the data folder: each archive is dropped as soon as it has been gone
+/// through.
+fn remote_sources(ranges: &[DateRange], source: &str, settings: FetchSettings) -> Result<Vec<QuerySource>> {
+    let hours: Vec<DateTime<Utc>> = match date_range::hours_of(ranges, Utc::now()) {
+        Ok(h) if !h.is_empty() => h,
+        Ok(..) => return Err(GarError::Query(
+            "a remote query needs a date range (--from/--to, --day, --month, --last or --range)".into())),
+        Err(e) => return Err(GarError::Query(format!("a remote query needs bounded dates: {}", e))),
+    };
+
+    hours.into_iter()
+         .map(|hour| archive_at(hour, source).map(|a| QuerySource::Remote(a, settings)))
          .collect()
+}
+
+/// What to query for `--input`: each of `inputs` is a file, a directory that is searched for
+/// archives (files with one of `ARCHIVE_EXTENSIONS`), or `-` for standard input. Files named after
+/// an hour are only read when that hour is in `ranges`. Other files, and standard input, are read
+/// in full, and their events filtered by their `created_at` date instead. The data folder, and
+/// its indexes and compacted archives, are left alone.
+fn input_sources(inputs: &[String], ranges: &[DateRange]) -> Result<Vec<QuerySource>> {
+    let mut v: Vec<QuerySource> = Vec::new();
+    let mut stdin: bool = false;
+
+    for input in inputs {
+        if input == "-" {
+            if stdin {
+                return Err(GarError::Query("standard input can only be given once to --input".into()));
+            }
+            stdin = true;
+            v.push(QuerySource::Stdin);
+            continue;
+        }
+
+        let p: PathBuf = PathBuf::from(input);
+        let files: Vec<PathBuf> = if p.is_dir() {
+            archives_under(&p)
+        }
+        else if p.is_file() {
+            vec![p]
+        }
+        else {
+            return Err(GarError::Query(format!("--input {}: no such file or directory", input)));
+        };
+
+        for f in files {
+            match archive_name_of(&f) {
+                Some((d, _)) if !date_range::any_contains(ranges, &d) => continue,
+                Some(..) => v.push(QuerySource::Input(f, false)),
+                None => v.push(QuerySource::Input(f, true)),
+            }
+        }
+    }
+
+    Ok(v)
+}
+
+/// The archives anywhere under `dir`, in chronological order for the ones named after an hour
+/// (which come after the others), and by path otherwise
+fn archives_under(dir: &Path) -> Vec<PathBuf> {
+    let mut v: Vec<(Option<DateTime<Utc>>, PathBuf)> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path().to_path_buf())
+        .filter(|p| p.is_file() && !is_temp_file(p) && has_archive_extension(p))
+        .map(|p| (date_from_path(&p), p))
+        .collect();
+
+    v.sort();
+    v.into_iter().map(|(_, p)| p).collect()
+}
+
+fn has_archive_extension(p: &Path) -> bool {
+    match p.file_name().and_then(|f| f.to_str()) {
+        Some(f) => ARCHIVE_EXTENSIONS.iter().any(|ext| f.ends_with(ext)),
+        None => false,
     }
 }
 
-fn get_data_file_paths() -> Vec<PathBuf> {
-    let p: PathBuf = data_path();
+/// This will look into the ~/.config/gar/data folder, and match the
+/// filenames against the given date ranges. If the match is successful,
+/// then the path to that archive is returned. Paths come back in
+/// chronological order, and files that aren't named after a date (with one of
+/// `ARCHIVE_EXTENSIONS`) are left out. When an hour is there with several
+/// extensions, the first one of `ARCHIVE_EXTENSIONS` is used.
+fn choose_files_from_dates(ranges: &[DateRange]) -> Result<Vec<PathBuf>> {
+    let mut v: Vec<(DateTime<Utc>, usize, PathBuf)> = get_data_file_paths()?
+        .into_iter()
+        .filter_map(|p| archive_name_of(&p).map(|(d, ext)| (d, ext, p)))
+        .filter(|(d, _, _)| date_range::any_contains(ranges, d))
+        .collect();
+
+    v.sort();
+    v.dedup_by_key(|(d, _, _)| *d);
+    Ok(v.into_iter().map(|(_, _, p)| p).collect())
+}
+
+/// The hour an archive is for, from its name (YYYY-mm-dd-h.json.gz, or another of
+/// `ARCHIVE_EXTENSIONS`)
+fn date_from_path(p: &Path) -> Option<DateTime<Utc>> {
+    archive_name_of(p).map(|(d, _)| d)
+}
+
+/// The hour an archive is for, and the index of its extension in `ARCHIVE_EXTENSIONS`
+fn archive_name_of(p: &Path) -> Option<(DateTime<Utc>, usize)> {
+    let filename = p.file_name().and_then(|f| f.to_str())?;
+
+    ARCHIVE_EXTENSIONS.iter().enumerate().find_map(|(ix, ext)| {
+        let date = filename.strip_suffix(ext)?;
+        date_range::parse_hour(date).ok().map(|d| (d, ix))
+    })
+}
+
+/// The archives of the data folder: files named after their hour (see `archive_name_of`). Temporary
+/// files, and whatever else ended up in there (notes, downloads under another name), are left out.
+fn get_data_file_paths() -> Result<Vec<PathBuf>> {
+    let p: PathBuf = data_path()?;
     let start = WalkDir::new(p);
     let mut v: Vec<PathBuf> = Vec::new();
 
     for entry in start.into_iter().filter_map(|e| e.ok()) {
-        let eisf = File::open(entry.path()).ok().unwrap()
-            .metadata().ok().unwrap().file_type().is_file();
-        if eisf {
+        let path: &Path = entry.path();
+        if path.is_file() && !is_temp_file(path) && archive_name_of(path).is_some() {
             v.push(entry.path().to_path_buf());
         }
     }
 
-    v
+    Ok(v)
 }
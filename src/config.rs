@@ -7,94 +7,310 @@ use std::fs;
 use std::fs::File;
 
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use error::{GarError, Result};
+use models::cache;
+use models::archive::FetchSettings;
+use models::cache::{EvictionOrder, Policy};
 
 const NAME: &'static str = "gar";
 const DATADIR: &'static str = "data";
+const QUARANTINEDIR: &'static str = "quarantine";
+const INDEXDIR: &'static str = "index";
+const INDEX_SUFFIX: &'static str = ".idx";
+const COMPACTDIR: &'static str = "compact";
+const COMPACT_SUFFIX: &'static str = ".gar";
 const PREFIX: &'static str = ".config";
 const CONFIG: &'static str = "gar.toml";
+const LOCK: &'static str = "data.lock";
+const TEMP_SUFFIX: &'static str = ".tmp";
+
+/// The file name endings of archives in the data folder, after the hour they are for (as in
+/// `2015-01-01-15.json.gz`). The compression is told from the contents, not from these, although
+/// `gar verify` reports a `.gz` or `.zst` archive whose contents say otherwise.
+pub const ARCHIVE_EXTENSIONS: &'static [&'static str] = &[
+    ".json.gz", ".json.zst", ".json", ".ndjson.gz", ".ndjson.zst", ".ndjson",
+];
+
+/// Where archives are downloaded from, unless told otherwise
+pub const DEFAULT_ARCHIVE_URL: &'static str = "https://data.githubarchive.org/";
+
+/// Environment variable that overrides the `source` key of the configuration
+pub const ARCHIVE_URL_VAR: &'static str = "GAR_ARCHIVE_URL";
+
+/// How many archives `fetch range` downloads at once, unless told otherwise
+pub const DEFAULT_JOBS: usize = 4;
+
+/// Seconds to wait for a connection to the archive server
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+
+/// Seconds to wait on a stalled read from the archive server
+pub const DEFAULT_READ_TIMEOUT: u64 = 60;
+
+/// How many times a failed download is retried before giving up on that hour
+pub const DEFAULT_RETRIES: u64 = 3;
+
+/// Delay before the first retry, in milliseconds; doubled on every following attempt, up to
+/// `MAX_BACKOFF_MS`
+pub const DEFAULT_BACKOFF_MS: u64 = 1000;
+
+/// The longest delay between two retries, in milliseconds, however many retries there are
+pub const MAX_BACKOFF_MS: u64 = 60_000;
 
-pub fn config_path() -> PathBuf {
+/// `~/.config/gar`; fails when `$HOME` is not set
+pub fn config_path() -> Result<PathBuf> {
     let home = match env::var("HOME") {
         Ok(v) => v,
-        Err(e) => {
-            println!("Could not get the HOME environment variable");
-            panic!(e);
-        },
+        Err(e) => return Err(GarError::Config(
+            format!("Could not get the HOME environment variable: {}", e))),
     };
     let mut path = PathBuf::new();
     path.push(home);
     path.push(PREFIX);
     path.push(NAME);
-    path
+    Ok(path)
 }
 
-pub fn data_path() -> PathBuf {
-    let mut base = config_path();
+pub fn data_path() -> Result<PathBuf> {
+    let mut base = config_path()?;
     base.push(DATADIR);
-    base
+    Ok(base)
 }
 
-pub fn config_file_path() -> PathBuf {
-    let mut base = config_path();
-    base.push(CONFIG);
-    base
+/// Where `gar verify --quarantine` moves archives that failed verification
+pub fn quarantine_path() -> Result<PathBuf> {
+    let mut base = config_path()?;
+    base.push(QUARANTINEDIR);
+    Ok(base)
 }
 
-fn read_configuration_file() -> Table {
-    let mut f: File = match File::open(config_file_path()) {
-        Ok(v) => v,
-        Err(e) => panic!(e),
+/// Where the archive indexes built by `gar index` (see `models::index`) are kept
+pub fn index_path() -> Result<PathBuf> {
+    let mut base = config_path()?;
+    base.push(INDEXDIR);
+    Ok(base)
+}
+
+/// The index file of the archive at `archive`, named after it
+pub fn index_file_path(archive: &Path) -> Result<PathBuf> {
+    derived_file_path(index_path()?, archive, INDEX_SUFFIX)
+}
+
+/// Where the archives converted by `gar compact` (see `models::compact`) are kept
+pub fn compact_path() -> Result<PathBuf> {
+    let mut base = config_path()?;
+    base.push(COMPACTDIR);
+    Ok(base)
+}
+
+/// The compacted version of the archive at `archive`, named after it
+pub fn compact_file_path(archive: &Path) -> Result<PathBuf> {
+    derived_file_path(compact_path()?, archive, COMPACT_SUFFIX)
+}
+
+/// A file in `dir` that holds something made out of the archive at `archive`
+fn derived_file_path(mut dir: PathBuf, archive: &Path, suffix: &str) -> Result<PathBuf> {
+    let filename = match archive.file_name().and_then(|f| f.to_str()) {
+        Some(f) => f,
+        None => return Err(GarError::Config(format!("{:?} is not an archive", archive))),
     };
+    dir.push(format!("{}{}", filename, suffix));
+    Ok(dir)
+}
+
+pub fn config_file_path() -> Result<PathBuf> {
+    let mut base = config_path()?;
+    base.push(CONFIG);
+    Ok(base)
+}
+
+/// The file the data folder lock (see `lock::DataLock`) is held on
+pub fn lock_file_path() -> Result<PathBuf> {
+    let mut base = config_path()?;
+    base.push(LOCK);
+    Ok(base)
+}
+
+/// Where an archive is written before it is renamed into the data folder `data`. It lives in the
+/// data folder itself so that the rename stays on the same filesystem, and so is atomic.
+pub fn temp_file_path(data: &Path, filename: &str) -> PathBuf {
+    data.join(format!(".{}.{}{}", filename, process::id(), TEMP_SUFFIX))
+}
+
+/// True for the files made by `temp_file_path`, which are never archives
+pub fn is_temp_file(p: &Path) -> bool {
+    match p.file_name().and_then(|f| f.to_str()) {
+        Some(f) => f.starts_with('.') && f.ends_with(TEMP_SUFFIX),
+        None => false,
+    }
+}
+
+fn read_configuration_file() -> Result<Table> {
+    let path: PathBuf = config_file_path()?;
+    let mut f: File = File::open(&path)?;
     let mut s: String = String::new();
-    f.read_to_string(&mut s).unwrap();
-
-    toml::Parser::new(&s).parse().unwrap()
-}
-
-pub fn caching_on() -> bool {
-    let t: Table = read_configuration_file();
-    match t.get(&"config".to_string()) {
-        Some(v) => {
-            match v {
-                &toml::Value::Table(ref vv) => {
-                    match vv.get(&"caching".to_string()) {
-                        Some(s) => match s {
-                            &toml::Value::String(ref s) => {
-                                s == "yes"
-                            },
-                            _ => false,
-                        },
-                        None => false,
-                    }
-                },
-                _ => false,
-            }
+    f.read_to_string(&mut s)?;
+
+    let mut parser = toml::Parser::new(&s);
+    match parser.parse() {
+        Some(t) => Ok(t),
+        None => {
+            let reasons: Vec<String> = parser.errors
+                .iter()
+                .map(|e| {
+                    let (line, col) = parser.to_linecol(e.lo);
+                    format!("{}:{}: {}", line + 1, col + 1, e.desc.replace('\n', "\\n"))
+                })
+                .collect();
+            Err(GarError::Config(format!("{} is not valid toml: {}",
+                                         path.to_str().unwrap_or(CONFIG), reasons.join("; "))))
         },
-        None => false,
     }
 }
 
-pub fn data_exists(filename: &String) -> bool {
-    let mut dpath: PathBuf = data_path();
-    dpath.push(filename);
-    dpath.exists()
+/// Size and age limits for the data folder, from the `max_size` (like "10G", or a number of
+/// bytes) and `max_age` (like "30d") keys of the configuration. Both are optional.
+pub fn cache_policy() -> Result<Policy> {
+    let max_size: Option<u64> = match config_value("max_size")? {
+        Some(toml::Value::String(s)) => Some(cache::parse_size(&s)?),
+        Some(toml::Value::Integer(n)) if n >= 0 => Some(n as u64),
+        Some(..) => return Err(GarError::Config("max_size should be a size, like \"10G\"".into())),
+        None => None,
+    };
+
+    let max_age: Option<Duration> = match config_value("max_age")? {
+        Some(toml::Value::String(s)) => Some(cache::parse_age(&s)?),
+        Some(..) => return Err(GarError::Config("max_age should be an age, like \"30d\"".into())),
+        None => None,
+    };
+
+    Ok(Policy { max_size, max_age })
+}
+
+/// Which archives to evict first, from the `prune_by` key ("access", the default, or "date")
+pub fn prune_order() -> Result<EvictionOrder> {
+    match config_value("prune_by")? {
+        Some(toml::Value::String(s)) => EvictionOrder::parse(&s),
+        Some(..) => Err(GarError::Config("prune_by should be \"access\" or \"date\"".into())),
+        None => Ok(EvictionOrder::LeastRecentAccess),
+    }
+}
+
+/// Whether fetches apply the cache policy once done (`auto_prune = "yes"`)
+pub fn auto_prune() -> Result<bool> {
+    config_yes_no(&config_table()?, "auto_prune")
+}
+
+pub fn caching_on() -> Result<bool> {
+    config_yes_no(&config_table()?, "caching")
+}
+
+/// The `[config]` table of the configuration file; empty when there is none
+fn config_table() -> Result<Table> {
+    let mut t: Table = read_configuration_file()?;
+    match t.remove("config") {
+        Some(toml::Value::Table(vv)) => Ok(vv),
+        Some(..) => Err(GarError::Config("[config] should be a table".into())),
+        None => Ok(Table::new()),
+    }
+}
+
+/// Look up a key in the `[config]` table of the configuration file
+fn config_value(key: &str) -> Result<Option<toml::Value>> {
+    Ok(config_table()?.remove(key))
+}
+
+/// A "yes"/"no" key of the `[config]` table `t`; missing means no
+fn config_yes_no(t: &Table, key: &str) -> Result<bool> {
+    match t.get(key) {
+        Some(toml::Value::String(s)) => Ok(s == "yes"),
+        Some(..) => Err(GarError::Config(format!("{} should be \"yes\" or \"no\"", key))),
+        None => Ok(false),
+    }
+}
+
+/// Integer value of a key in the `[config]` table `t`, or `default` when it is missing
+fn config_integer(t: &Table, key: &str, default: u64) -> Result<u64> {
+    match t.get(key) {
+        Some(&toml::Value::Integer(n)) if n >= 0 => Ok(n as u64),
+        Some(..) => Err(GarError::Config(format!("{} should be a positive integer", key))),
+        None => Ok(default),
+    }
+}
+
+/// Number of concurrent downloads for `fetch range`, from the `jobs` key of the configuration.
+/// Falls back to `DEFAULT_JOBS` when it is missing.
+pub fn fetch_jobs() -> Result<usize> {
+    match config_integer(&config_table()?, "jobs", DEFAULT_JOBS as u64)? {
+        0 => Err(GarError::Config("jobs should be a positive integer".into())),
+        n => Ok(n as usize),
+    }
+}
+
+/// The base url archives are fetched from. The first one of these that is set wins: `flag` (the
+/// `--source` command line flag), the `GAR_ARCHIVE_URL` environment variable, the `source` key of
+/// the configuration, and finally `DEFAULT_ARCHIVE_URL`. Both `http(s)://` and `file://` urls
+/// are valid; a trailing `/` is added if missing.
+pub fn archive_url(flag: Option<String>) -> Result<String> {
+    let configured: Option<String> = match config_value("source")? {
+        Some(toml::Value::String(s)) => Some(s),
+        Some(..) => return Err(GarError::Config("source should be a url".into())),
+        None => None,
+    };
+
+    let mut url: String = flag
+        .or_else(|| env::var(ARCHIVE_URL_VAR).ok())
+        .or(configured)
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| DEFAULT_ARCHIVE_URL.into());
+
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+
+    Ok(url)
+}
+
+/// How archives are fetched: the `caching`, `retries`, `backoff_ms`, `connect_timeout` and
+/// `read_timeout` (both in seconds) keys of the configuration, read in one go. Fetches read these
+/// once, when they start, so that every archive of a range is fetched the same way.
+pub fn fetch_settings() -> Result<FetchSettings> {
+    let t: Table = config_table()?;
+
+    Ok(FetchSettings {
+        caching: config_yes_no(&t, "caching")?,
+        retries: config_integer(&t, "retries", DEFAULT_RETRIES)?,
+        backoff: Duration::from_millis(config_integer(&t, "backoff_ms", DEFAULT_BACKOFF_MS)?),
+        connect_timeout: Duration::from_secs(
+            config_integer(&t, "connect_timeout", DEFAULT_CONNECT_TIMEOUT)?),
+        read_timeout: Duration::from_secs(config_integer(&t, "read_timeout", DEFAULT_READ_TIMEOUT)?),
+    })
+}
+
+/// True if the data folder has an archive for `hour` (as in `2015-01-01-15`), under any of the
+/// `ARCHIVE_EXTENSIONS`
+pub fn archive_exists(hour: &str) -> Result<bool> {
+    let dpath: PathBuf = data_path()?;
+    Ok(ARCHIVE_EXTENSIONS.iter().any(|ext| dpath.join(format!("{}{}", hour, ext)).exists()))
 }
 
 /// Default things to run each time we go through the main entry point.
-pub fn init() -> () {
-    let cpath: PathBuf = config_path();
-    let dpath: PathBuf = data_path();
-    let config_file: PathBuf = config_file_path();
+pub fn init() -> Result<()> {
+    let cpath: PathBuf = config_path()?;
+    let dpath: PathBuf = data_path()?;
+    let config_file: PathBuf = config_file_path()?;
 
     if !cpath.exists() {
         println!("Config file path created for the first time");
-        fs::create_dir_all(cpath.to_str().unwrap()).unwrap();
+        fs::create_dir_all(&cpath)?;
     }
 
     if !dpath.exists() {
         println!("Data path created for the first time");
-        fs::create_dir_all(dpath.to_str().unwrap()).unwrap();
+        fs::create_dir_all(&dpath)?;
     }
 
     if !config_file.exists() {
@@ -102,18 +318,16 @@ pub fn init() -> () {
         let mut caching_table: Table = toml::Table::new();
 
         caching_table.insert("caching".to_string(), toml::Value::String("yes".to_string()));
+        caching_table.insert("jobs".to_string(), toml::Value::Integer(DEFAULT_JOBS as i64));
         t.insert("config".to_string(), toml::Value::Table(caching_table));
 
         let s: String = toml::encode_str(&t);
 
-        let mut f: File = match File::create(config_file) {
-            Ok(v)  => v,
-            Err(e) => panic!("Could not open config file for writing {}", e),
-        };
+        let mut f: File = File::create(config_file)?;
 
         println!("Writing configuration for the first time");
-        f.write_all(s.as_bytes()).unwrap();
+        f.write_all(s.as_bytes())?;
     }
-}
-
 
+    Ok(())
+}
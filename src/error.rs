@@ -0,0 +1,63 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+use rusqlite;
+
+/// Everything that can go wrong in gar. Library functions return these instead of panicking or
+/// printing, so that programs embedding gar can decide what to do about them.
+#[derive(Debug)]
+pub enum GarError {
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// An archive could not be downloaded
+    Http(String),
+    /// Some input (a date, an archive, a json line) could not be understood
+    Parse(String),
+    /// The configuration, or the environment it comes from, is unusable
+    Config(String),
+    /// A query is malformed (bad constraint, regex or number)
+    Query(String),
+    /// A handlebars template could not be loaded, compiled or rendered
+    Template(String),
+    /// Events could not be written to an export database
+    Export(String),
+}
+
+pub type Result<T> = result::Result<T, GarError>;
+
+impl fmt::Display for GarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GarError::Io(ref e) => write!(f, "io error: {}", e),
+            GarError::Http(ref s) => write!(f, "download error: {}", s),
+            GarError::Parse(ref s) => write!(f, "parse error: {}", s),
+            GarError::Config(ref s) => write!(f, "configuration error: {}", s),
+            GarError::Query(ref s) => write!(f, "query error: {}", s),
+            GarError::Template(ref s) => write!(f, "template error: {}", s),
+            GarError::Export(ref s) => write!(f, "export error: {}", s),
+        }
+    }
+}
+
+impl error::Error for GarError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            GarError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GarError {
+    fn from(e: io::Error) -> GarError {
+        GarError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for GarError {
+    fn from(e: rusqlite::Error) -> GarError {
+        GarError::Export(e.to_string())
+    }
+}
@@ -16,10 +16,15 @@
 //!     -v, --version    show the current version
 //!
 //! SUBCOMMANDS:
+//!     cache    for managing the downloaded archives
+//!     compact  for converting the downloaded archives into a format that is faster to query
+//!     export   for exporting events to other tools
 //!     fetch    for fetching singular files
 //!     help     Prints this message
+//!     index    for indexing the downloaded archives, so that queries can skip some
 //!     query    for running queries on the retrieved data
 //!     show     for printing different program information
+//!     verify   for checking the integrity of the downloaded archives
 //! ````
 //!
 //! # Fetching
@@ -57,6 +62,83 @@
 //! gar fetch range --from 2013-1-1-1 --to 2013-1-5-1
 //! ````
 //!
+//! Both ends of a range are included. Besides `--from` and `--to`, `fetch range` and `query` take the
+//! same date arguments, and any of them can be combined:
+//!
+//! * `--day 2015-01-01`, a whole day (or `today`, `yesterday`)
+//! * `--month 2015-01`, a whole month
+//! * `--last 48h` or `--last 7d`, that many hours or days up to now
+//! * `--range <date>..<date>`, which can be given several times; either side can be left out when
+//!   querying
+//!
+//! Dates can be written as `YYYY-m-d-h` (the archive names), `2015-01-01T15` or any RFC 3339 date for
+//! an hour, `2015-01-01`, `2015-01` or `2015` for a whole day, month or year, or as `now`, `today` or
+//! `yesterday`. When a date that spans more than an hour is given to `--from`, the range starts at its
+//! first hour; given to `--to`, it ends at its last hour:
+//!
+//! ````nocode
+//! gar fetch range --from 2015-01-01 --to 2015-01-02
+//! gar query --range 2014-12-30..2014-12-31 --range 2015-01-05T00..2015-01-05T06 --where type:push
+//! ````
+//!
+//! Ranges are downloaded a few archives at a time. You can change how many with `--jobs`, or set
+//! the default with the `jobs` key in the `[config]` table of `gar.toml`:
+//!
+//! ````nocode
+//! gar fetch range --from 2013-1-1-1 --to 2013-1-5-1 --jobs 8
+//! ````
+//!
+//! Downloads that time out, or that the server answers with an error, are retried a few times with a
+//! growing delay. An hour that still fails is reported, and the rest of the range carries on. Once
+//! done, gar prints how many hours were fetched, skipped (already cached) and failed, and exits with a
+//! non zero status if any failed. These keys of the `[config]` table tune this behaviour:
+//!
+//! * `connect_timeout`, seconds to wait for a connection (default 10)
+//! * `read_timeout`, seconds to wait on a stalled download (default 60)
+//! * `retries`, how many times to retry an hour (default 3)
+//! * `backoff_ms`, delay before the first retry in milliseconds, doubled after each attempt (default
+//!   1000)
+//!
+//! Archives come from `https://data.githubarchive.org/` by default. To fetch from a mirror instead,
+//! give its base url with `--source`, the `GAR_ARCHIVE_URL` environment variable, or the `source` key
+//! of the `[config]` table, in that order of precedence. Both `http(s)://` and `file://` urls work, the
+//! latter for a plain folder of archives:
+//!
+//! ````nocode
+//! gar fetch range --from 2013-1-1-1 --to 2013-1-5-1 --source file:///srv/githubarchive
+//! ````
+//!
+//! # Verifying
+//!
+//! Archives that were cut short, or got corrupted on disk, can be found with:
+//!
+//! ````nocode
+//! gar verify
+//! ````
+//!
+//! This decompresses every archive in the data folder, checking its gzip trailers (or zstd checksums)
+//! on the way, and counts the events in it. Add `--quarantine` to move bad archives out of the data
+//! folder (into `~/.config/gar/quarantine`), and `--repair` to download them again.
+//!
+//! The data folder can be kept within limits by setting `max_size` (like `"10G"`, `"500M"`, or a
+//! number of bytes) and/or `max_age` (like `"30d"`, `"12h"` or `"4w"`) in the `[config]` table, and
+//! running:
+//!
+//! ````nocode
+//! gar cache prune --dry-run
+//! gar cache prune
+//! ````
+//!
+//! Archives that haven't been read for longer than `max_age` are removed first. Then, if the rest is
+//! still over `max_size`, more are removed, either the least recently read first (`--by access`, the
+//! default) or the oldest hours first (`--by date`). The default order can be set with `prune_by`.
+//! With `auto_prune = "yes"`, every fetch prunes the data folder once it is done.
+//!
+//! Downloads are written to a temporary file and renamed into place once complete, so an interrupted
+//! fetch never leaves a partial archive in the data folder. Commands that write to the data folder
+//! (`fetch`, `verify --quarantine/--repair`, `cache prune`) also take a lock on it, so a second one started
+//! meanwhile waits for the first to finish. Queries are not held up by the lock.
+//!
 //! # Querying
 //!
 //! You can run simple queries this way:
@@ -70,11 +152,23 @@
 //!
 //! FLAGS:
 //!     -h, --help       Prints help information
+//!         --ordered    with --jobs, print results in chronological order instead of as they come
+//!         --remote     download and query each archive in memory, without caching it
 //!     -V, --version    Prints version information
 //!
 //! OPTIONS:
+//!         --day <day>...           query a whole day, as YYYY-mm-dd
+//!         --format <format>        output the selected fields as csv (default) or tsv [values: csv, tsv]
 //!     -f, --from <from>            specify query date in YYYY-mm-dd-h format
-//!     -s, --select <select>        specify which fields to output
+//!     -i, --input <input>...       query a file, a directory of archives, or - for standard input,
+//!                                  instead of the data folder
+//!     -j, --jobs <jobs>            how many archives to query at once
+//!         --last <last>            query the last hours or days, like 48h or 7d
+//!         --month <month>...       query a whole month, as YYYY-mm
+//!         --progress=<progress>    report progress on stderr, as text (when stderr is a terminal) or json
+//!         --range <range>...       query a range, as <date>..<date>
+//!     -s, --select <select>        specify which fields to output, as comma separated columns
+//!         --source <source>        base url (http(s):// or file://) to fetch archives from
 //!     -m, --template <template>    specify handlebar template for output
 //!     -t, --to <to>                specify query date in YYYY-mm-dd-h format
 //!     -w, --where <where>          specify selection constraints
@@ -90,6 +184,60 @@
 //!
 //!   gar query --where language:Rust,type:create --from 2013-1-1-1 --to 2013-1-5-1
 //!
+//! To go through a range once without filling up the data folder, add `--remote`. Each hour is then
+//! queried as it downloads, without being held in memory whole; nothing is read from or written to
+//! the data folder:
+//!
+//!   gar query --remote --day 2015-01-01 --where type:release
+//!
+//! Long ranges can be queried on several cores with `--jobs`, which decompresses, parses and filters
+//! that many archives at once. Results are then printed as they come, so hours get mixed together;
+//! add `--ordered` to get them in chronological order, as a single job would print them:
+//!
+//!   gar query --month 2015-01 --where type:release --jobs 8 --ordered
+//!
+//! Long fetches and queries can report how far along they are on stderr with `--progress`: files
+//! done out of how many, bytes read, events per second, matches so far and the time left. The report
+//! is only shown when stderr is a terminal. For scripts, `--progress=json` writes one json object per
+//! second instead, whatever stderr is, and a last one with `"done": true`:
+//!
+//!   gar query --month 2015-01 --where type:release --jobs 8 --progress=json 2> progress.log
+//!
+//! Rather than one debug dump per event, `--select` prints the chosen fields, separated by commas. They
+//! come out in the order given, under a header row, as csv, or as tsv with `--format tsv`; values are
+//! quoted where needed, so the output can go straight into a spreadsheet. `--format` alone selects
+//! every field; fields are the same as the handlebars labels listed further down:
+//!
+//!   gar query --where language:Rust --select id,name,language,created_at,event_type > rust.csv
+//!
+//! Queries skip the archives that can't have anything to match, going by a small index of each
+//! archive: the languages, owners, repository names and event types found in it, with event counts.
+//! An archive that has no index yet (or that changed since it was indexed) is read in full, and gets
+//! its index on the way. To index the whole data folder up front, run:
+//!
+//!   gar index
+//!
+//! Add `--force` to index every archive again. Indexes live in `~/.config/gar/index`; the ones of
+//! archives that are gone are removed by `gar index`.
+//!
+//! Decompressing and parsing the json of each archive is most of the time a query takes. Archives
+//! that are queried often can be converted once into a compact binary format, that gar reads much
+//! faster:
+//!
+//!   gar compact
+//!
+//! Queries then read the compacted version of an archive, and fall back to the json one for archives
+//! that have none, or that changed since they were converted. Add `--force` to convert every archive
+//! again. Compacted archives live in `~/.config/gar/compact`, and don't count towards `max_size`.
+//!
+//! Files outside of the data folder can be queried in place with `--input`, which takes a file, a
+//! directory (searched for archives, in subdirectories too), or `-` for events piped in, and can be
+//! given several times. The data folder is then left alone. Files named after an hour, like the
+//! archives of the data folder, are only read when that hour is within the dates of the query; other
+//! files are read in full, and their events are picked by the date they happened at:
+//!
+//!   zcat dump.json.gz | gar query --input - --input /mnt/archives/2015 --day 2015-01-01 --where type:release
+//!
 //! And as you noticed you can also provide a type of event, and language using the `--where` clause.
 //! The way you do this, is by providing a label, delimited with a colon `:` and provide the value.
 //! For example:
@@ -101,19 +249,48 @@
 //!
 //!   language:Rust,type:create
 //!
+//! Constraints can also be combined with `AND` (same as the comma), `OR` and `NOT`, and grouped with
+//! parentheses. `NOT` applies to what follows it first, then `AND` (and commas) are done before `OR`.
+//! For example, to get the Rust or Go repositories that aren't forks:
+//!
+//!   (language:Rust OR language:Go) AND NOT type:fork
+//!
+//! A value goes on until the next comma, the closing parenthesis, or an `AND` or `OR` after a space,
+//! so it can hold spaces (`commit_comment:fix the build`). A backslash keeps the character after it
+//! from ending the value, as in `description:fast\, safe`. Values can also be quoted, in which case
+//! they are taken as is, even by the labels that otherwise take a regex; within quotes, `\"` is a
+//! quote and `\\` a backslash:
+//!
+//!   description:"fast, safe (and fun)"
+//!
+//! Regexes can also be written between slashes, followed by `i` to ignore case. Unlike the values of
+//! `name` and `description`, these are case sensitive unless told otherwise, and they work with
+//! `language`, `owner` and `actor` too:
+//!
+//!   name:/^rust-.*$/i OR language:/^(c|c\+\+)$/i
+//!
+//! When the clause can't be understood, the error shows where:
+//!
+//!   gar: query error: column 1: unclosed '('
+//!       (language:Rust OR language:Go AND NOT type:fork
+//!       ^
+//!
 //! Here's the list of things you can add as constraints:
 //!
 //! These are the event types you can capture using these labels:
 //!
 //! * language:`value`, where value is the exact name of the language (case sensitive)
 //! * owner:`value`, where value is the exact name of the owner (case sensitive)
+//! * actor:`value`, where value is the exact login of the user that triggered the event (case
+//!   sensitive)
 //! * name:`regex-value`, where value is the (part) name of the repository (regex)
 //! * description:`regex-value`, where value is part of the comments, with a regex match
-//! * +watchers:`value`, where value is an integer. Matches against repos which have watchers more
-//! than or equal to the given value.
-//! * -watchers:`value`, where value is an integer. Matches against repositories which have less
-//! watchers than the value provided.
-//! * +stargazers:`value`,-stargazers:`value`, same as `watchers` above.
+//! * id, watchers, stargazers, forks, open_issues: numbers of the repository, compared with any
+//!   operator (see below), like `watchers>=100` or `forks:10..20`
+//! * +watchers:`value` and -watchers:`value`, older spellings of `watchers>=value` and
+//!   `watchers<value`; same for `+stargazers` and `-stargazers`
+//! * created_at: when the event happened, compared with any operator to the dates `--range` takes,
+//!   like `created_at>=2015-01-15`
 //! * type:`event-type` where `event-type` is:
 //!     * create
 //!     * commit_comment
@@ -141,8 +318,33 @@
 //!     * team_add
 //!     * watch
 //!
-//! * commit_comment:<regex-value>, where value is the text to be matched, case insensitive against
-//! the given commits. This will only work against event types of `push`.
+//! * Labels on the payload of one event type, which events of other types never satisfy (not even
+//!   with `!=`):
+//!     * commit_comment:<regex-value>, where value is the text to be matched, case insensitive
+//!       against the comments of the commits of a `push`
+//!     * push.ref, push.head and push.size, the branch, head commit and number of commits of a
+//!       `push`: `push.ref:refs/heads/main`, `push.size>=10`
+//!     * issue.action and issue.number, for `issues`: `issue.action:closed`
+//!     * watch.action, for `watch`: `watch.action:started`
+//!     * delete.ref and delete.ref_type, for `delete`: `delete.ref_type:branch`
+//!     * gollum.page and gollum.action, for `gollum`, which hold when any of the edited wiki pages
+//!       matches: `gollum.page:Home`
+//!
+//! Besides `:`, constraints can use `=` (which is the same), `!=`, `<`, `<=`, `>` and `>=`. `!=` works
+//! with every label, and holds whenever `:` wouldn't; the others only compare numbers and
+//! `created_at`. With `:`, `=` and `!=`, numbers and dates can also be given a range `N..M`, which
+//! includes both ends like `--range` does, and either end of which can be left out: `forks:10..20`,
+//! `open_issues!=1..`. A date stands for all the hours it spans, so `created_at:2015-01` is any time
+//! in January 2015, `created_at<=2015-01` anything up to the end of it, and `created_at>2015-01` from
+//! February on. Events without a date never satisfy a `created_at` constraint.
+//!
+//! The whole clause is checked before any archive is read, so that a typo can't quietly match
+//! everything: unknown labels and event types, and bad regexes, numbers or dates are errors. Labels
+//! and event types that are almost right get a suggestion:
+//!
+//!   gar: query error: column 15: unknown event type 'pussh', did you mean 'push'?
+//!       language:Rust,type:pussh
+//!                     ^
 //!
 //! # Handlebars
 //!
@@ -200,6 +402,58 @@
 //! * watchers
 //! * stargazers
 //! * forks
+//! * open_issues
+//! * actor
+//! * org
+//! * public
+//! * event_type
+//! * created_at
+//!
+//! # Exporting
+//!
+//! To go further than `--where` allows, events can be exported to a SQLite database, and queried with
+//! plain SQL:
+//!
+//!   gar export sqlite --out events.db --month 2015-01 --where language:Rust
+//!
+//! Without dates, every archive of the data folder is exported. The database gets one table per kind
+//! of thing, with indexes on the columns you would join or filter on:
+//!
+//! * `events`: type, date (UTC, as `YYYY-mm-dd HH:MM:SS`), repository, actor and organization, and the
+//!   repository counters (watchers, stargazers, forks, open issues) at the time of the event
+//! * `repos`: keyed by their github id, with their owner, name, url, description and language
+//! * `users`: the logins of owners, actors and organizations
+//! * `pushes` and `push_commits`, `issues`, `issue_comments` and `gollum_pages`: the payloads of
+//!   those events, keyed by `event_id`
+//! * `archives`: which archives were exported, and with what `--where` clause
+//!
+//! Exports are incremental: archives that were already exported, with the same `--where` clause, are
+//! skipped, so running the same command again after fetching more hours only adds the new ones. An
+//! archive that changed since (say, one downloaded again) replaces what was exported of it.
+//!
+//!   sqlite3 events.db "SELECT r.name, COUNT(*) FROM events e JOIN repos r ON r.id = e.repo_id GROUP BY r.name"
+//!
+//! # Archive formats
+//!
+//! githubarchive changed its format at the start of 2015. Older archives describe the full repository
+//! with each event (`repository`), while newer ones follow the GitHub Events API (`repo`, `actor`,
+//! `org`). gar detects the format of each line, and fills the same fields from either one, so the same
+//! query can span both eras. Keep in mind that the newer format carries no repository statistics:
+//! `language`, `description`, `watchers`, `stargazers`, `forks` and `has_issues` are empty for events
+//! from 2015 onwards.
+//!
+//! Archives don't have to be gzip files straight from githubarchive. gar looks at the first bytes of
+//! each file to tell how it is stored, and reads gzip (including several gzip files concatenated
+//! together), zstd, and plain uncompressed json, one event per line. In the data folder, archives are
+//! named after their hour with any of the `.json.gz`, `.json.zst`, `.json`, `.ndjson.gz`,
+//! `.ndjson.zst` or `.ndjson` extensions; files named otherwise are not queried.
+//!
+//! # Errors
+//!
+//! The public functions of the library (`config`, `cli`, and the `models`) don't panic on bad input or
+//! a broken environment: they return a `gar::error::GarError`, which says what kind of problem it was
+//! (`Io`, `Http`, `Parse`, `Config`, `Query`, `Template` or `Export`). The `gar` binary prints it and
+//! exits with status 1.
 
 extern crate rustc_serialize;
 extern crate time;
@@ -212,10 +466,17 @@ extern crate term;
 extern crate flate2;
 extern crate walkdir;
 extern crate handlebars;
+extern crate fs2;
+extern crate zstd;
+extern crate rusqlite;
+extern crate csv;
 
+pub mod error;
 pub mod models;
 pub mod config;
 pub mod cli;
+pub mod lock;
+pub mod progress;
 
 #[inline]
 fn print_yellow(s: &str) -> () {
@@ -229,10 +490,14 @@ fn print_green(s: &str) -> () {
 
 #[inline]
 fn print_red(s: &str) -> () {
-    let mut t = term::stderr().unwrap();
-    t.fg(term::color::RED).unwrap();
-    write!(t, "{}", s).unwrap();
-    t.reset().unwrap();
+    /* No colours when there is no terminal to speak of (eg: TERM is not set) */
+    let mut t = match term::stderr() {
+        Some(t) => t,
+        None => return eprint!("{}", s),
+    };
+    let _ = t.fg(term::color::RED);
+    let _ = write!(t, "{}", s);
+    let _ = t.reset();
 }
 
 #[inline]
@@ -242,10 +507,13 @@ fn print_magenta(s: &str) -> () {
 
 #[inline]
 fn generic_print(s: &str, col: term::color::Color) -> () {
-    let mut t = term::stdout().unwrap();
-    t.fg(col).unwrap();
-    write!(t, "{}", s).unwrap();
-    t.reset().unwrap();
+    let mut t = match term::stdout() {
+        Some(t) => t,
+        None => return print!("{}", s),
+    };
+    let _ = t.fg(col);
+    let _ = write!(t, "{}", s);
+    let _ = t.reset();
 }
 
 fn vec_contains<T: PartialEq>(v: &Vec<T>, t: &T) -> bool {
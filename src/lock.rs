@@ -0,0 +1,106 @@
+use fs2::FileExt;
+
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+use config;
+use error::Result;
+
+/// Advisory lock over the data directory. Anything that writes to, moves, or removes cached
+/// archives holds one for as long as it runs, so that two gar processes (say, a fetch from cron and
+/// one from the shell) never write the same files at once. Readers don't need it: archives only
+/// ever appear in the data directory through an atomic rename, so a reader sees either the whole
+/// file or no file.
+///
+/// The lock is released when this is dropped, or when the process dies.
+pub struct DataLock {
+    file: File,
+}
+
+impl DataLock {
+    /// Take the lock, waiting for any other gar process that holds it.
+    pub fn acquire() -> Result<DataLock> {
+        DataLock::acquire_at(&config::lock_file_path()?, &config::data_path()?)
+    }
+
+    /// `acquire`, with the lock file at `path`, for the data folder `data`
+    fn acquire_at(path: &Path, data: &Path) -> Result<DataLock> {
+        let file: File = DataLock::open(path)?;
+
+        if file.try_lock_exclusive().is_err() {
+            ::print_yellow("Another gar process is writing to the data folder - waiting\n");
+            file.lock_exclusive()?;
+        }
+
+        DataLock::remove_stale_temp_files(data);
+
+        Ok(DataLock { file })
+    }
+
+    /// The lock file at `path`, made if there is none yet
+    fn open(path: &Path) -> Result<File> {
+        Ok(OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?)
+    }
+
+    /// Temporary files left behind in `data` by an interrupted download. Only called with the lock
+    /// held, so nobody else can be writing them.
+    fn remove_stale_temp_files(data: &Path) {
+        let entries = match fs::read_dir(data) {
+            Ok(e) => e,
+            Err(..) => return,
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            if config::is_temp_file(&entry.path()) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+impl Drop for DataLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fs2::FileExt;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use config;
+    use lock::*;
+
+    #[test]
+    fn test_lock() -> () {
+        let data: PathBuf = env::temp_dir().join(format!("gar-lock-{}", ::std::process::id()));
+        fs::create_dir_all(&data).unwrap();
+        let path: PathBuf = data.join("gar.lock");
+
+        /* What an interrupted download leaves behind, next to an archive that must stay */
+        let stale: PathBuf = config::temp_file_path(&data, "2015-01-01-0.json.gz");
+        fs::write(&stale, b"partial").unwrap();
+        fs::write(data.join("2015-01-01-1.json.gz"), b"whole").unwrap();
+
+        let lock = DataLock::acquire_at(&path, &data).unwrap();
+        assert!(!stale.exists());
+        assert!(data.join("2015-01-01-1.json.gz").exists());
+
+        /* Nobody else gets the lock while it is held, and anybody does once it is dropped */
+        let other = DataLock::open(&path).unwrap();
+        assert!(other.try_lock_exclusive().is_err());
+        drop(lock);
+        assert!(other.try_lock_exclusive().is_ok());
+        FileExt::unlock(&other).unwrap();
+
+        fs::remove_dir_all(&data).unwrap();
+    }
+}
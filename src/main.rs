@@ -2,17 +2,56 @@ extern crate walkdir;
 extern crate gar;
 #[macro_use] extern crate clap;
 
+use clap::ArgMatches;
+
 use gar::cli;
 use gar::config;
+use gar::error;
+use gar::models::date_range::DateArgs;
+
+/// The value of `r`, or else report the error and quit
+fn or_exit<T>(r: error::Result<T>) -> T {
+    match r {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("gar: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// The date arguments that `fetch range` and `query` share
+fn date_args(matches: &ArgMatches) -> DateArgs {
+    fn many(matches: &ArgMatches, name: &str) -> Vec<String> {
+        matches.values_of(name).map(|v| v.map(|e| e.into()).collect()).unwrap_or_default()
+    }
+
+    DateArgs {
+        from: matches.value_of("from").map(|e| e.into()),
+        to: matches.value_of("to").map(|e| e.into()),
+        days: many(matches, "day"),
+        months: many(matches, "month"),
+        last: matches.value_of("last").map(|e| e.into()),
+        ranges: many(matches, "range"),
+    }
+}
+
+/// The value of `--progress`, which is "text" when the flag is given without one
+fn progress_arg(matches: &ArgMatches) -> Option<String> {
+    if !matches.is_present("progress") { return None }
+
+    Some(matches.value_of("progress").unwrap_or("text").into())
+}
 
 fn main() {
-    config::init();
+    or_exit(config::init());
 
     let matches = clap_app!(myapp =>
         (version: env!("CARGO_PKG_VERSION"))
         (author: "Simon psyomn Symeonidis <lethaljellybean@gmail.com>")
         (about: "Github Archive interfacing and querying tool")
         (@arg version: -v --version "show the current version")
+        (@arg source: --source +takes_value +global "base url (http(s):// or file://) to fetch archives from")
         (@subcommand show =>
             (about: "for printing different program information")
             (@arg data: -d --data "shows tha data folder")
@@ -23,30 +62,88 @@ fn main() {
             (@arg file: --file +takes_value "the date in YYYY-mm-dd-h format")
             (@subcommand range =>
                 (about: "for fetching from certain dates")
-                (@arg from: -f --from +takes_value "the date in YYYY-mm-dd-h format")
-                (@arg to:   -t --to   +takes_value "the date in YYYY-mm-dd-h format")
+                (@arg from: -f --from +takes_value "first hour to fetch, as YYYY-mm-dd-h or an ISO date")
+                (@arg to:   -t --to   +takes_value "last hour to fetch, as YYYY-mm-dd-h or an ISO date")
+                (@arg day:  --day +takes_value +multiple number_of_values(1) "fetch a whole day, as YYYY-mm-dd")
+                (@arg month: --month +takes_value +multiple number_of_values(1) "fetch a whole month, as YYYY-mm")
+                (@arg last: --last +takes_value "fetch the last hours or days, like 48h or 7d")
+                (@arg range: --range +takes_value +multiple number_of_values(1) "fetch a range, as <date>..<date>")
+                (@arg jobs: -j --jobs +takes_value "how many archives to download at once")
+                (@arg progress: --progress +takes_value min_values(0) require_equals(true) "report progress on stderr, as text (when stderr is a terminal) or json")
+            )
+        )
+        (@subcommand verify =>
+            (about: "for checking the integrity of the downloaded archives")
+            (@arg quarantine: -q --quarantine "move bad archives to the quarantine folder")
+            (@arg repair: -r --repair "download bad archives again")
+        )
+        (@subcommand index =>
+            (about: "for indexing the downloaded archives, so that queries can skip some")
+            (@arg force: --force "index every archive again, even those with an up to date index")
+        )
+        (@subcommand compact =>
+            (about: "for converting the downloaded archives into a format that is faster to query")
+            (@arg force: --force "convert every archive again, even those already converted")
+        )
+        (@subcommand export =>
+            (about: "for exporting events to other tools")
+            (@subcommand sqlite =>
+                (about: "append events to a SQLite database, one table per kind of thing")
+                (@arg out: -o --out +takes_value +required "the database to create or append to")
+                (@arg from:  -f --from +takes_value "first hour to export, as YYYY-mm-dd-h or an ISO date")
+                (@arg to:    -t --to   +takes_value "last hour to export, as YYYY-mm-dd-h or an ISO date")
+                (@arg day:   --day +takes_value +multiple number_of_values(1) "export a whole day, as YYYY-mm-dd")
+                (@arg month: --month +takes_value +multiple number_of_values(1) "export a whole month, as YYYY-mm")
+                (@arg last:  --last +takes_value "export the last hours or days, like 48h or 7d")
+                (@arg range: --range +takes_value +multiple number_of_values(1) "export a range, as <date>..<date>")
+                (@arg where: -w --where +takes_value "only export the events that satisfy these constraints")
+            )
+        )
+        (@subcommand cache =>
+            (about: "for managing the downloaded archives")
+            (@subcommand prune =>
+                (about: "remove archives until the data folder meets max_size and max_age")
+                (@arg by: --by +takes_value possible_value[access date] "evict by least recent access, or oldest date first")
+                (@arg dry_run: -n --("dry-run") "only show what would be removed")
             )
         )
         (@subcommand query =>
             (about: "for running queries on the retrieved data")
             (@arg from:   -f --from +takes_value "specify query date in YYYY-mm-dd-h format")
             (@arg to:     -t --to +takes_value "specify query date in YYYY-mm-dd-h format")
-            (@arg select: -s --select +takes_value "specify which fields to output")
+            (@arg day:    --day +takes_value +multiple number_of_values(1) "query a whole day, as YYYY-mm-dd")
+            (@arg month:  --month +takes_value +multiple number_of_values(1) "query a whole month, as YYYY-mm")
+            (@arg last:   --last +takes_value "query the last hours or days, like 48h or 7d")
+            (@arg range:  --range +takes_value +multiple number_of_values(1) "query a range, as <date>..<date>")
+            (@arg select: -s --select +takes_value "specify which fields to output, as comma separated columns")
+            (@arg format: --format +takes_value possible_value[csv tsv] "output the selected fields as csv (default) or tsv")
             (@arg where:  -w --where +takes_value "specify selection constraints")
             (@arg template: -m --template +takes_value "specify handlebar template for output")
+            (@arg remote: --remote "download and query each archive in memory, without caching it")
+            (@arg jobs:   -j --jobs +takes_value "how many archives to query at once")
+            (@arg ordered: --ordered "with --jobs, print results in chronological order instead of as they come")
+            (@arg input:  -i --input +takes_value +multiple number_of_values(1) "query a file, a directory of archives, or - for standard input, instead of the data folder")
+            (@arg progress: --progress +takes_value min_values(0) require_equals(true) "report progress on stderr, as text (when stderr is a terminal) or json")
         )
     ).get_matches();
 
     if let Some(matches) = matches.subcommand_matches("fetch") {
         if matches.is_present("file") {
             let filename = matches.value_of("file").unwrap();
-            cli::fetch(filename.into());
+            let source = matches.value_of("source").map(|e| e.into());
+            if !or_exit(cli::fetch(filename.into(), source)).is_success() {
+                std::process::exit(1);
+            }
             return;
         }
         if let Some(matches) = matches.subcommand_matches("range") {
-            let from = matches.value_of("from").map(|e| e.into());
-            let to = matches.value_of("to").map(|e| e.into());
-            cli::fetch_rng(from, to);
+            let dates = date_args(matches);
+            let jobs = matches.value_of("jobs").map(|e| e.into());
+            let source = matches.value_of("source").map(|e| e.into());
+            let progress = progress_arg(matches);
+            if !or_exit(cli::fetch_rng(dates, jobs, source, progress)).is_success() {
+                std::process::exit(1);
+            }
             return;
         }
     }
@@ -55,11 +152,58 @@ fn main() {
         /* gar show --data
          * gar show --paths */
         if matches.is_present("data") {
-            cli::ls_data();
+            or_exit(cli::ls_data());
             return;
         }
         if matches.is_present("paths") {
-            cli::show_paths();
+            or_exit(cli::show_paths());
+            return;
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("verify") {
+        let quarantine: bool = matches.is_present("quarantine");
+        let repair: bool = matches.is_present("repair");
+        let source = matches.value_of("source").map(|e| e.into());
+        if !or_exit(cli::verify(quarantine, repair, source)) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("index") {
+        if !or_exit(cli::index(matches.is_present("force"))) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("compact") {
+        if !or_exit(cli::compact(matches.is_present("force"))) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("export") {
+        if let Some(matches) = matches.subcommand_matches("sqlite") {
+            let dates: DateArgs = date_args(matches);
+            let wheres: Option<String> = matches.value_of("where").map(|e| e.into());
+            let out: String = matches.value_of("out").unwrap().into();
+            if !or_exit(cli::export_sqlite(dates, wheres, out)) {
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("cache") {
+        if let Some(matches) = matches.subcommand_matches("prune") {
+            let by: Option<String> = matches.value_of("by").map(|e| e.into());
+            let dry_run: bool = matches.is_present("dry_run");
+            if !or_exit(cli::prune(by, dry_run)) {
+                std::process::exit(1);
+            }
             return;
         }
     }
@@ -70,12 +214,20 @@ fn main() {
     }
 
     if let Some(matches) = matches.subcommand_matches("query") {
-        let selects: Option<String> = matches.value_of("select").map(|e| e.into());
-        let wheres: Option<String> = matches.value_of("where").map(|e| e.into());
-        let from: Option<String> = matches.value_of("from").map(|e| e.into());
-        let to: Option<String> = matches.value_of("to").map(|e| e.into());
-        let template: Option<String> = matches.value_of("template").map(|e| e.into());
-        cli::find(from, to, selects, wheres, template);
+        let dates: DateArgs = date_args(matches);
+        let opts = cli::QueryOptions {
+            selects: matches.value_of("select").map(|e| e.into()),
+            wheres: matches.value_of("where").map(|e| e.into()),
+            template: matches.value_of("template").map(|e| e.into()),
+            format: matches.value_of("format").map(|e| e.into()),
+            remote: matches.is_present("remote"),
+            source: matches.value_of("source").map(|e| e.into()),
+            jobs: matches.value_of("jobs").map(|e| e.into()),
+            ordered: matches.is_present("ordered"),
+            inputs: matches.values_of("input").map(|v| v.map(|e| e.into()).collect()).unwrap_or_default(),
+            progress: progress_arg(matches),
+        };
+        or_exit(cli::find(dates, opts));
         return;
     }
 }
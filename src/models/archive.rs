@@ -1,13 +1,19 @@
 use chrono::*;
+use attohttpc::{ErrorKind, ResponseReader, StatusCode};
 
+use std::fs;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{self, SystemTime, UNIX_EPOCH};
 
 use std::io::Write;
 
 use config;
+use error::{GarError, Result};
 
-const GITHUT_ARCHIVE_URL: &'static str = "https://data.githubarchive.org/";
+const FILE_SCHEME: &'static str = "file://";
 
 /// An archive is a file object - not to be confused with repos, or things that will give us access
 /// to data.
@@ -16,26 +22,96 @@ pub struct Archive {
     date: DateTime<Utc>,
     data: Vec<u8>,
     name: String,
+    source: String,
+}
+
+/// What happened when we tried to fetch an archive.
+#[derive(Debug)]
+pub enum FetchStatus {
+    /// Downloaded from the server
+    Fetched,
+    /// Already present in the data directory, so nothing was downloaded
+    Cached,
+    /// The server does not have this archive
+    NotFound,
+    /// The download failed, even after retrying; holds the reason
+    Failed(GarError),
+}
+
+/// How archives are fetched (see `config::fetch_settings`)
+#[derive(Debug, Clone, Copy)]
+pub struct FetchSettings {
+    /// Keep fetched archives in the data folder
+    pub caching: bool,
+    /// How many times a transient failure is retried
+    pub retries: u64,
+    /// The wait before the first retry, which doubles with each one, up to
+    /// `config::MAX_BACKOFF_MS`
+    pub backoff: time::Duration,
+    pub connect_timeout: time::Duration,
+    pub read_timeout: time::Duration,
+}
+
+/// Tally of a fetch run, by hour (archive name)
+#[derive(Debug, Default)]
+pub struct FetchSummary {
+    pub succeeded: Vec<String>,
+    pub skipped: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+impl FetchSummary {
+    pub fn record(&mut self, name: &str, status: &FetchStatus) {
+        match *status {
+            FetchStatus::Fetched => self.succeeded.push(name.into()),
+            FetchStatus::Cached => self.skipped.push(name.into()),
+            FetchStatus::NotFound => self.failed.push((name.into(), "not found on server".into())),
+            FetchStatus::Failed(ref e) => self.failed.push((name.into(), e.to_string())),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Result of one download attempt, with the body (or a reader for it) when it went through
+enum Attempt<T> {
+    Body(T),
+    Missing,
+    /// Worth trying again (timeouts, connection resets, 5xx)
+    Retry(String),
+    /// No point in trying again (bad url, 4xx)
+    GiveUp(String),
 }
 
 pub struct ArchiveBuilder {
     year: i32,
     month: u32,
     day: u32,
-    hour: u32
+    hour: u32,
+    source: String,
 }
 
 impl Archive {
 
-    pub fn new(y: i32, m: u32, d: u32, h: u32) -> Archive {
-        let d = Utc.ymd(y, m, d).and_hms(h, 0, 0);
+    /// The archive for the given hour; fails if there is no such hour
+    pub fn new(y: i32, m: u32, d: u32, h: u32) -> Result<Archive> {
+        let d = Archive::make_hour(y, m, d, h)?;
         let n = Archive::make_title(d);
 
-        Archive {
+        Ok(Archive {
             date: d,
             data: vec![],
             name: n,
-        }
+            source: config::DEFAULT_ARCHIVE_URL.into(),
+        })
+    }
+
+    fn make_hour(y: i32, m: u32, d: u32, h: u32) -> Result<DateTime<Utc>> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0)
+            .single()
+            .ok_or_else(|| GarError::Parse(format!("there is no hour {}-{}-{}-{}", y, m, d, h)))
     }
 
     fn make_date(d: DateTime<Utc>) -> String {
@@ -46,78 +122,254 @@ impl Archive {
         format!("{}.json.gz", Archive::make_date(d))
     }
 
-    fn fetch_raw(url: &String) -> Vec<u8> {
-        match attohttpc::get(&url).send() {
-            Ok(raw) => raw.bytes().unwrap(),
-            Err(e) => panic!(e),
+    /// Set the base url the archive is fetched from (see `config::archive_url`)
+    pub fn set_source(&mut self, source: String) {
+        self.source = source;
+    }
+
+    /// The full url of this archive, on the server it is fetched from
+    pub fn url(&self) -> String {
+        format!("{}{}", self.source, self.name)
+    }
+
+    /// Read the archive at `url`, which is either a `file://` or an `http(s)://` url. `Ok(None)`
+    /// means there is no such archive.
+    fn fetch_raw(url: &str, settings: &FetchSettings) -> Result<Option<Vec<u8>>> {
+        if let Some(path) = url.strip_prefix(FILE_SCHEME) {
+            Archive::read_raw(Path::new(path))
+        }
+        else if url.starts_with("http://") || url.starts_with("https://") {
+            Archive::download_raw(url, settings)
+        }
+        else {
+            Err(GarError::Config(format!("unsupported archive url {}", url)))
         }
     }
 
-    /// Fetch the information of a specific archive. This will return something in memory, and will
-    /// not make a local copy.
-    pub fn fetch(&mut self) -> () {
-        let title: String = Archive::make_title(self.date);
+    /// Read an archive from a plain directory (a mirror, or test fixtures)
+    fn read_raw(p: &Path) -> Result<Option<Vec<u8>>> {
+        match fs::read(p) {
+            Ok(b) => Ok(Some(b)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(GarError::Io(e)),
+        }
+    }
+
+    /// Download the file at `url`, retrying transient failures with exponential backoff and
+    /// jitter. `Ok(None)` means the server has no such file.
+    fn download_raw(url: &str, settings: &FetchSettings) -> Result<Option<Vec<u8>>> {
+        Archive::retrying(url, settings, Archive::try_fetch_raw)
+    }
 
-        if config::data_exists(&title) {
-            ::print_yellow(format!("Data {} exists in cache - skip\n", title).as_ref());
-            return;
+    /// Make `try_once` attempts at `url`, with exponential backoff and jitter between them, until
+    /// one goes through or there is no point in going on
+    fn retrying<T, F>(url: &str, settings: &FetchSettings, try_once: F) -> Result<Option<T>>
+        where F: Fn(&str, (time::Duration, time::Duration)) -> Attempt<T> {
+        let retries: u64 = settings.retries;
+        let max_delay: time::Duration = time::Duration::from_millis(config::MAX_BACKOFF_MS);
+        let mut delay: time::Duration = settings.backoff.min(max_delay);
+        let timeouts = (settings.connect_timeout, settings.read_timeout);
+        let mut attempt: u64 = 0;
+
+        loop {
+            match try_once(url, timeouts) {
+                Attempt::Body(b) => return Ok(Some(b)),
+                Attempt::Missing => return Ok(None),
+                Attempt::GiveUp(e) => return Err(GarError::Http(e)),
+                Attempt::Retry(e) => {
+                    if attempt >= retries {
+                        return Err(GarError::Http(
+                            format!("{} (gave up after {} attempts)", e, attempt + 1)));
+                    }
+                    thread::sleep(delay + Archive::jitter(delay));
+                    delay = delay.saturating_mul(2).min(max_delay);
+                    attempt += 1;
+                },
+            }
         }
+    }
 
-        let url: String = format!("{}{}", GITHUT_ARCHIVE_URL, title);
+    /// `timeouts` are the connect and read timeouts
+    fn try_fetch_raw(url: &str, timeouts: (time::Duration, time::Duration)) -> Attempt<Vec<u8>> {
+        match Archive::try_open_raw(url, timeouts) {
+            Attempt::Body(reader) => match reader.bytes() {
+                Ok(b) => Attempt::Body(b),
+                Err(e) => Attempt::Retry(e.to_string()),
+            },
+            Attempt::Missing => Attempt::Missing,
+            Attempt::Retry(e) => Attempt::Retry(e),
+            Attempt::GiveUp(e) => Attempt::GiveUp(e),
+        }
+    }
+
+    /// Connect to `url` and check the answer of the server, leaving the body to be read
+    fn try_open_raw(url: &str, timeouts: (time::Duration, time::Duration)) -> Attempt<ResponseReader> {
+        let response = attohttpc::get(url)
+            .connect_timeout(timeouts.0)
+            .read_timeout(timeouts.1)
+            .send();
+
+        let response = match response {
+            Ok(r) => r,
+            Err(e) => return match *e.kind() {
+                ErrorKind::Io(..) | ErrorKind::InvalidResponse(..) => Attempt::Retry(e.to_string()),
+                _ => Attempt::GiveUp(e.to_string()),
+            },
+        };
 
-        self.data = Archive::fetch_raw(&url);
+        let status: StatusCode = response.status();
 
-        if &self.data[0..5] == b"<?xml" {
-            ::print_magenta(format!("\nNo such info found on server ({})\n", url).as_ref());
-            return;
+        if status.is_success() {
+            Attempt::Body(response.split().2)
+        }
+        else if status == StatusCode::NOT_FOUND {
+            Attempt::Missing
+        }
+        else if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            Attempt::Retry(format!("server answered {}", status))
         }
         else {
-            ::print_green(format!(" ok\n").as_ref());
+            Attempt::GiveUp(format!("server answered {}", status))
         }
+    }
+
+    /// Some extra delay of up to `d`, so that workers that failed together don't all retry at
+    /// the same moment.
+    fn jitter(d: time::Duration) -> time::Duration {
+        let ms = d.as_millis() as u64;
+        if ms == 0 { return time::Duration::from_millis(0) }
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.subsec_nanos() as u64)
+            .unwrap_or(0);
 
-        if config::caching_on() { self.store() }
+        time::Duration::from_millis(nanos % ms)
     }
 
-    pub fn store(&self) -> () {
-        let mut base: PathBuf = config::data_path();
-        let s: String = match base.clone().to_str() {
-            Some(v) => v.to_string(),
-            None => return,
-        };
+    /// The file name of the archive, as found on the server and in the data directory
+    pub fn name(&self) -> &String {
+        &self.name
+    }
 
-        base.push(self.name.clone());
+    /// The contents of the archive, once fetched or downloaded
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 
-        let mut f: File = match File::create(base) {
-            Ok(v) => v,
-            Err(e) => {
-                println!("Problem opening caching file @ {:?}", s);
-                println!("{}", e);
-                return;
+    /// Download the archive into memory only: the data folder is neither looked at nor written
+    /// to, whatever the caching setting. The contents are then available through `data`.
+    pub fn download(&mut self, settings: &FetchSettings) -> FetchStatus {
+        match Archive::fetch_raw(&self.url(), settings) {
+            Ok(Some(data)) => {
+                self.data = data;
+                FetchStatus::Fetched
             },
+            Ok(None) => FetchStatus::NotFound,
+            Err(e) => FetchStatus::Failed(e),
+        }
+    }
+
+    /// Open the archive to be read as it downloads, rather than held in memory whole as with
+    /// `download`; queries that go through it once use this. Connecting, and the answer of the
+    /// server, are retried as for `download`; a failure once the body is coming in ends the read.
+    /// `Ok(None)` means there is no such archive.
+    pub fn open(&self, settings: &FetchSettings) -> Result<Option<Box<dyn io::Read>>> {
+        let url: String = self.url();
+
+        if let Some(path) = url.strip_prefix(FILE_SCHEME) {
+            match File::open(path) {
+                Ok(f) => Ok(Some(Box::new(f))),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(GarError::Io(e)),
+            }
+        }
+        else if url.starts_with("http://") || url.starts_with("https://") {
+            let body = Archive::retrying(&url, settings, Archive::try_open_raw)?;
+            Ok(body.map(|r| Box::new(r) as Box<dyn io::Read>))
+        }
+        else {
+            Err(GarError::Config(format!("unsupported archive url {}", url)))
+        }
+    }
+
+    /// Fetch the information of a specific archive. This will return something in memory, and will
+    /// not make a local copy unless `settings` say to. Nothing is printed here, so that archives can
+    /// be fetched from several threads; it is up to the caller to report the returned status.
+    pub fn fetch(&mut self, settings: &FetchSettings) -> FetchStatus {
+        match self.try_fetch(settings) {
+            Ok(status) => status,
+            Err(e) => FetchStatus::Failed(e),
+        }
+    }
+
+    fn try_fetch(&mut self, settings: &FetchSettings) -> Result<FetchStatus> {
+        if config::archive_exists(&Archive::make_date(self.date))? {
+            return Ok(FetchStatus::Cached);
+        }
+
+        let url: String = self.url();
+
+        self.data = match Archive::fetch_raw(&url, settings)? {
+            Some(data) => data,
+            None => return Ok(FetchStatus::NotFound),
         };
 
-        f.write_all(&self.data).unwrap();
+        if settings.caching {
+            self.store()?;
+        }
+
+        Ok(FetchStatus::Fetched)
+    }
+
+    /// Write the archive into the data folder. The data goes to a temporary file first, which is
+    /// then renamed over the final name, so an interrupted write never leaves a partial archive
+    /// behind that would later pass for a cached one. Callers should hold a `lock::DataLock`.
+    pub fn store(&self) -> Result<()> {
+        self.store_in(&config::data_path()?)
+    }
+
+    /// `store`, into the data folder `data`
+    fn store_in(&self, data: &Path) -> Result<()> {
+        let dest: PathBuf = data.join(&self.name);
+        let tmp: PathBuf = config::temp_file_path(data, &self.name);
+
+        let written = File::create(&tmp)
+            .and_then(|mut f| {
+                f.write_all(&self.data)?;
+                f.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp, &dest));
+
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+
+        Ok(written?)
     }
 
     /// Set the year of the archive we're interested in
-    pub fn set_year(&mut self, year: i32) -> () {
-        self.date = Utc.ymd(year, self.date.month(), self.date.day())
-                       .and_hms(9, 0, 0);
+    pub fn set_year(&mut self, year: i32) -> Result<()> {
+        self.date = Archive::make_hour(year, self.date.month(), self.date.day(), 9)?;
+        Ok(())
     }
 
     /// Set the month of the archive we're interested in
-    pub fn set_month(&mut self, month: u32) -> () {
-        self.date = Utc.ymd(self.date.year(), month, self.date.day()).and_hms(9, 0, 0);
+    pub fn set_month(&mut self, month: u32) -> Result<()> {
+        self.date = Archive::make_hour(self.date.year(), month, self.date.day(), 9)?;
+        Ok(())
     }
 
     /// Set the day of the archive we're interested in
-    pub fn set_day(&mut self, day: u32) -> () {
-        self.date = Utc.ymd(self.date.year(), self.date.month(), day).and_hms(9, 0, 0);
+    pub fn set_day(&mut self, day: u32) -> Result<()> {
+        self.date = Archive::make_hour(self.date.year(), self.date.month(), day, 9)?;
+        Ok(())
     }
 
     /// Set the hour of the archive we're interested in
-    pub fn set_hour(&mut self, h: u32) -> () {
-        self.date = Utc.ymd(self.date.year(), self.date.month(), self.date.day()).and_hms(h, 0, 0);
+    pub fn set_hour(&mut self, h: u32) -> Result<()> {
+        self.date = Archive::make_hour(self.date.year(), self.date.month(), self.date.day(), h)?;
+        Ok(())
     }
 }
 
@@ -128,6 +380,7 @@ impl ArchiveBuilder {
             month: 0,
             day: 0,
             hour: 0,
+            source: config::DEFAULT_ARCHIVE_URL.into(),
         }
     }
 
@@ -151,11 +404,49 @@ impl ArchiveBuilder {
         self
     }
 
-    pub fn finalize(&self) -> Archive {
-        Archive::new(
+    pub fn source(&mut self, s: &str) -> &mut ArchiveBuilder {
+        self.source = s.into();
+        self
+    }
+
+    pub fn finalize(&self) -> Result<Archive> {
+        let mut a = Archive::new(
             self.year,
             self.month,
             self.day,
-            self.hour)
+            self.hour)?;
+        a.set_source(self.source.clone());
+        Ok(a)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use config;
+    use models::archive::*;
+
+    #[test]
+    fn test_store() -> () {
+        let data: PathBuf = env::temp_dir().join(format!("gar-store-{}", ::std::process::id()));
+        fs::create_dir_all(&data).unwrap();
+
+        let mut archive = Archive::new(2015, 1, 1, 0).unwrap();
+        let dest: PathBuf = data.join(archive.name());
+        fs::write(&dest, b"old").unwrap();
+
+        /* The new data replaces the old file whole, through a temporary file that is gone after */
+        archive.data = b"new".to_vec();
+        archive.store_in(&data).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"new");
+        assert!(!config::temp_file_path(&data, archive.name()).exists());
+
+        let names: Vec<PathBuf> = fs::read_dir(&data).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(names, vec![dest]);
+
+        fs::remove_dir_all(&data).unwrap();
     }
 }
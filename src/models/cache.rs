@@ -0,0 +1,170 @@
+use chrono::*;
+
+use std::path::PathBuf;
+use std::time::{self, SystemTime};
+
+use error::{GarError, Result};
+
+/// An archive in the data folder, as far as eviction is concerned.
+#[derive(Debug, Clone)]
+pub struct CachedArchive {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Last access time, or modification time where the filesystem doesn't keep access times
+    pub accessed: SystemTime,
+    /// The hour the archive is for
+    pub date: DateTime<Utc>,
+}
+
+/// Which archives go first when the data folder is over its size budget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvictionOrder {
+    /// The ones that have gone the longest without being read
+    LeastRecentAccess,
+    /// The ones for the oldest hours
+    OldestDate,
+}
+
+impl EvictionOrder {
+    pub fn parse(s: &str) -> Result<EvictionOrder> {
+        match s {
+            "access" => Ok(EvictionOrder::LeastRecentAccess),
+            "date" => Ok(EvictionOrder::OldestDate),
+            _ => Err(GarError::Config(format!("invalid eviction order {:?}: expected access or date", s))),
+        }
+    }
+}
+
+/// Limits on the data folder, from the `max_size` and `max_age` keys of the configuration.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// Total size, in bytes, that the archives may take up
+    pub max_size: Option<u64>,
+    /// How long an archive may go without being read
+    pub max_age: Option<time::Duration>,
+}
+
+impl Policy {
+    pub fn is_empty(&self) -> bool {
+        self.max_size.is_none() && self.max_age.is_none()
+    }
+}
+
+/// The archives to remove so that the rest meet `policy`: first every archive that wasn't read
+/// within `max_age`, then more in `order` until the total fits in `max_size`.
+pub fn plan(mut archives: Vec<CachedArchive>, policy: &Policy, order: EvictionOrder,
+            now: SystemTime) -> Vec<CachedArchive> {
+    match order {
+        EvictionOrder::LeastRecentAccess => archives.sort_by_key(|a| a.accessed),
+        EvictionOrder::OldestDate => archives.sort_by_key(|a| a.date),
+    }
+
+    let expired = |a: &CachedArchive| match policy.max_age {
+        Some(age) => now.duration_since(a.accessed).map(|d| d > age).unwrap_or(false),
+        None => false,
+    };
+
+    let (mut evict, keep): (Vec<CachedArchive>, Vec<CachedArchive>) =
+        archives.into_iter().partition(|a| expired(a));
+
+    let mut total: u64 = keep.iter().map(|a| a.size).sum();
+
+    if let Some(max) = policy.max_size {
+        for a in keep {
+            if total <= max { break }
+            total -= a.size;
+            evict.push(a);
+        }
+    }
+
+    evict
+}
+
+/// A size such as `500M`, `10G` or a plain number of bytes. Units are powers of 1024.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let err = || GarError::Config(
+        format!("invalid size {:?}: expected a number of bytes, or one like 500M or 10G", s));
+
+    let s = s.trim();
+    let (num, mult): (&str, u64) = match s.chars().last() {
+        Some('K') | Some('k') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') | Some('g') => (&s[..s.len() - 1], 1 << 30),
+        Some('T') | Some('t') => (&s[..s.len() - 1], 1 << 40),
+        _ => (s, 1),
+    };
+
+    num.trim().parse::<u64>().ok().and_then(|n| n.checked_mul(mult)).ok_or_else(err)
+}
+
+/// An age such as `12h`, `30d` or `4w`
+pub fn parse_age(s: &str) -> Result<time::Duration> {
+    let err = || GarError::Config(format!("invalid age {:?}: expected one like 12h, 30d or 4w", s));
+
+    let s = s.trim();
+    if s.len() < 2 { return Err(err()) }
+
+    let (num, unit) = s.split_at(s.len() - 1);
+    let n: u64 = num.parse().map_err(|_| err())?;
+    let hours: Option<u64> = match unit {
+        "h" => Some(n),
+        "d" => n.checked_mul(24),
+        "w" => n.checked_mul(24 * 7),
+        _ => return Err(err()),
+    };
+
+    hours.and_then(|h| h.checked_mul(3600)).map(time::Duration::from_secs).ok_or_else(err)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::*;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use models::cache::*;
+
+    fn archive(name: &str, size: u64, accessed: u64, hour: u32) -> CachedArchive {
+        CachedArchive {
+            path: PathBuf::from(name),
+            size,
+            accessed: UNIX_EPOCH + Duration::from_secs(accessed),
+            date: Utc.with_ymd_and_hms(2015, 1, 1, hour, 0, 0).unwrap(),
+        }
+    }
+
+    fn names(v: Vec<CachedArchive>) -> Vec<String> {
+        v.into_iter().map(|a| a.path.to_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn test_plan() -> () {
+        let now = UNIX_EPOCH + Duration::from_secs(100_000);
+        let archives = vec![archive("a", 10, 90_000, 3),
+                            archive("b", 10, 50_000, 1),
+                            archive("c", 10, 99_000, 2)];
+
+        let size = Policy { max_size: Some(15), max_age: None };
+        assert_eq!(names(plan(archives.clone(), &size, EvictionOrder::LeastRecentAccess, now)), vec!["b", "a"]);
+        assert_eq!(names(plan(archives.clone(), &size, EvictionOrder::OldestDate, now)), vec!["b", "c"]);
+
+        let age = Policy { max_size: None, max_age: Some(Duration::from_secs(20_000)) };
+        assert_eq!(names(plan(archives.clone(), &age, EvictionOrder::OldestDate, now)), vec!["b"]);
+
+        assert!(plan(archives, &Policy::default(), EvictionOrder::OldestDate, now).is_empty());
+    }
+
+    #[test]
+    fn test_parse_size_and_age() -> () {
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("2K").unwrap(), 2048);
+        assert_eq!(parse_size("10G").unwrap(), 10 * 1024 * 1024 * 1024);
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("99999999999T").is_err());
+
+        assert_eq!(parse_age("2d").unwrap(), Duration::from_secs(2 * 24 * 3600));
+        assert!(parse_age("2y").is_err());
+        assert!(parse_age("9999999999999999w").is_err());
+        assert!(parse_age("9999999999999999h").is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use config;
+use error::{GarError, Result};
+use models::event::Event;
+use models::index::stamp_of;
+use models::reader::Counted;
+
+/// First bytes of every compacted archive
+const MAGIC: &[u8; 4] = b"GARC";
+
+/// Bumped whenever the encoding of anything below changes. Compacted archives of another version
+/// are ignored, as if there were none, until `gar compact` is run again.
+pub const COMPACT_VERSION: u8 = 1;
+
+/// Something that can be written to, and read back from, a compacted archive. Fields are written
+/// one after the other, in declaration order, with no names or padding.
+pub trait Compact: Sized {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()>;
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<Self>;
+}
+
+/// Writes the building blocks of the format: integers as LEB128 varints (zigzagged when signed),
+/// strings and lists prefixed with their length, and options with a 0/1 tag.
+pub struct Encoder<W: Write> {
+    w: W,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder { w }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    /// Bytes as they are, with no length
+    pub fn raw(&mut self, b: &[u8]) -> io::Result<()> {
+        self.w.write_all(b)
+    }
+
+    pub fn u8(&mut self, n: u8) -> io::Result<()> {
+        self.raw(&[n])
+    }
+
+    pub fn u64(&mut self, mut n: u64) -> io::Result<()> {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                return self.u8(byte);
+            }
+            self.u8(byte | 0x80)?;
+        }
+    }
+
+    pub fn i64(&mut self, n: i64) -> io::Result<()> {
+        self.u64(((n << 1) ^ (n >> 63)) as u64)
+    }
+
+    pub fn bool(&mut self, b: bool) -> io::Result<()> {
+        self.u8(b as u8)
+    }
+
+    pub fn str(&mut self, s: &str) -> io::Result<()> {
+        self.u64(s.len() as u64)?;
+        self.w.write_all(s.as_bytes())
+    }
+
+    pub fn option<T: Compact>(&mut self, o: &Option<T>) -> io::Result<()> {
+        match *o {
+            Some(ref v) => {
+                self.u8(1)?;
+                v.encode(self)
+            },
+            None => self.u8(0),
+        }
+    }
+
+    pub fn list<T: Compact>(&mut self, v: &[T]) -> io::Result<()> {
+        self.u64(v.len() as u64)?;
+        for e in v {
+            e.encode(self)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads back what an `Encoder` wrote. Anything that doesn't add up is a parse error naming
+/// `origin`.
+pub struct Decoder<R: Read> {
+    r: R,
+    origin: String,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(r: R, origin: &str) -> Decoder<R> {
+        Decoder { r, origin: origin.into() }
+    }
+
+    /// An error about the data being read
+    pub fn corrupt(&self, what: &str) -> GarError {
+        GarError::Parse(format!("bad compacted archive {}: {}", self.origin, what))
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        let mut b = [0u8; 1];
+        match self.r.read_exact(&mut b) {
+            Ok(..) => Ok(b[0]),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(self.corrupt("truncated")),
+            Err(e) => Err(GarError::Io(e)),
+        }
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        let mut n: u64 = 0;
+        let mut shift: u32 = 0;
+
+        loop {
+            let byte = self.u8()?;
+            if shift > 63 {
+                return Err(self.corrupt("integer too large"));
+            }
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn i64(&mut self) -> Result<i64> {
+        let n = self.u64()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(self.corrupt("bad boolean")),
+        }
+    }
+
+    pub fn string(&mut self) -> Result<String> {
+        let len = self.u64()?;
+        let mut bytes: Vec<u8> = Vec::new();
+
+        (&mut self.r).take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(self.corrupt("truncated"));
+        }
+
+        String::from_utf8(bytes).map_err(|_| self.corrupt("string is not utf-8"))
+    }
+
+    pub fn option<T: Compact>(&mut self) -> Result<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(self)?)),
+            _ => Err(self.corrupt("bad option tag")),
+        }
+    }
+
+    pub fn list<T: Compact>(&mut self) -> Result<Vec<T>> {
+        let len = self.u64()?;
+        /* Don't trust the length with an allocation before the elements are actually there */
+        let mut v: Vec<T> = Vec::with_capacity(len.min(1024) as usize);
+        for _ in 0..len {
+            v.push(T::decode(self)?);
+        }
+        Ok(v)
+    }
+}
+
+impl<R: BufRead> Decoder<R> {
+    /// True once everything has been read
+    fn at_end(&mut self) -> Result<bool> {
+        Ok(self.r.fill_buf()?.is_empty())
+    }
+}
+
+impl Compact for String {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.str(self)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<String> {
+        d.string()
+    }
+}
+
+/// The events of a compacted archive, decoded one at a time. Like `ArchiveLines`, a problem with the
+/// file ends the iteration with one last `Err`.
+pub struct CompactEvents<R: Read> {
+    decoder: Option<Decoder<BufReader<R>>>,
+}
+
+impl<R: Read> Iterator for CompactEvents<R> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Result<Event>> {
+        let decoded = {
+            let d = self.decoder.as_mut()?;
+            match d.at_end() {
+                Ok(true) => None,
+                Ok(false) => Some(Event::decode(d)),
+                Err(e) => Some(Err(e)),
+            }
+        };
+
+        match decoded {
+            Some(Ok(e)) => Some(Ok(e)),
+            other => {
+                self.decoder = None;
+                other
+            },
+        }
+    }
+}
+
+/// The compacted version of the archive at `archive`, ready to be read, if there is one that was
+/// made from the archive as it is now
+pub fn open_fresh(archive: &Path) -> Option<CompactEvents<Counted<File>>> {
+    let path: PathBuf = config::compact_file_path(archive).ok()?;
+    let origin: String = path.to_str().unwrap_or("[uncapable of unwraping]").into();
+    let mut decoder = Decoder::new(BufReader::new(Counted(File::open(&path).ok()?)), &origin);
+
+    let stamp: (u64, u64) = read_header(&mut decoder).ok()?;
+    if stamp_of(archive).ok()? != stamp {
+        return None;
+    }
+
+    Some(CompactEvents { decoder: Some(decoder) })
+}
+
+/// True if the archive at `archive` has an up to date compacted version
+pub fn is_fresh(archive: &Path) -> bool {
+    open_fresh(archive).is_some()
+}
+
+/// Convert the json archive at `archive` into its compacted version, and return how many events
+/// it holds. The file is written under a temporary name and renamed into place, as with
+/// `Archive::store`.
+pub fn write(archive: &Path) -> Result<u64> {
+    let dest: PathBuf = config::compact_file_path(archive)?;
+    fs::create_dir_all(config::compact_path()?)?;
+
+    let mut tmp: PathBuf = dest.clone();
+    tmp.set_extension(format!("{}.tmp", std::process::id()));
+
+    let written = write_to(archive, &tmp).and_then(|n| {
+        fs::rename(&tmp, &dest)?;
+        Ok(n)
+    });
+
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    written
+}
+
+fn write_to(archive: &Path, dest: &Path) -> Result<u64> {
+    /* Stamped before reading, so that a change halfway through leaves the file already stale */
+    let (size, modified) = stamp_of(archive)?;
+    let mut e = Encoder::new(BufWriter::new(File::create(dest)?));
+    let mut count: u64 = 0;
+
+    e.raw(MAGIC)?;
+    e.u8(COMPACT_VERSION)?;
+    e.u64(size)?;
+    e.u64(modified)?;
+
+    for ev in Event::iter_json_path(archive.to_path_buf()) {
+        ev?.encode(&mut e)?;
+        count += 1;
+    }
+
+    let f: File = e.into_inner().into_inner().map_err(|e| GarError::Io(e.into_error()))?;
+    f.sync_all()?;
+
+    Ok(count)
+}
+
+/// Check the magic bytes and version, and return the stamp of the archive it was made from
+fn read_header<R: Read>(d: &mut Decoder<R>) -> Result<(u64, u64)> {
+    for m in MAGIC.iter() {
+        if d.u8()? != *m {
+            return Err(d.corrupt("not a compacted archive"));
+        }
+    }
+    if d.u8()? != COMPACT_VERSION {
+        return Err(d.corrupt("made by another version of gar"));
+    }
+
+    Ok((d.u64()?, d.u64()?))
+}
+
+/// Remove the compacted version of the archive at `archive`, if there is one
+pub fn remove(archive: &Path) {
+    if let Ok(path) = config::compact_file_path(archive) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use models::compact::*;
+    use models::event::Event;
+
+    #[test]
+    fn test_primitives() -> () {
+        let mut e = Encoder::new(Vec::new());
+        e.u64(0).unwrap();
+        e.u64(300).unwrap();
+        e.u64(u64::MAX).unwrap();
+        e.i64(-1_420_070_400).unwrap();
+        e.str("héllo").unwrap();
+        e.option(&Some(String::from("x"))).unwrap();
+        e.list::<String>(&[]).unwrap();
+        let bytes = e.into_inner();
+
+        let mut d = Decoder::new(Cursor::new(bytes.clone()), "test");
+        assert_eq!(d.u64().unwrap(), 0);
+        assert_eq!(d.u64().unwrap(), 300);
+        assert_eq!(d.u64().unwrap(), u64::MAX);
+        assert_eq!(d.i64().unwrap(), -1_420_070_400);
+        assert_eq!(d.string().unwrap(), "héllo");
+        assert_eq!(d.option::<String>().unwrap(), Some("x".into()));
+        assert!(d.list::<String>().unwrap().is_empty());
+        assert!(d.u8().is_err());
+
+        let mut d = Decoder::new(Cursor::new(bytes[..bytes.len() - 5].to_vec()), "test");
+        d.u64().unwrap();
+        d.u64().unwrap();
+        d.u64().unwrap();
+        d.i64().unwrap();
+        assert!(d.string().is_err());
+    }
+
+    #[test]
+    fn test_event_round_trip() -> () {
+        let lines = [
+            r#"{"created_at":"2013-01-01T01:00:00-08:00","public":true,"type":"PushEvent","actor":"jon",
+                "payload":{"head":"abc","ref":"refs/heads/master","size":1,
+                           "shas":[["abc","a@b.c","Fix the thing","jon",true]]},
+                "repository":{"id":724712,"name":"rust","owner":"mozilla","language":"Rust","watchers":10}}"#,
+            r#"{"type":"GollumEvent","created_at":"2015-01-01T15:00:00Z","actor":{"login":"jon"},
+                "repo":{"id":1,"name":"psyomn/gar"},
+                "payload":{"pages":[{"page_name":"Home","action":"edited","sha":"abc","summary":null}]}}"#,
+            r#"{"type":"IssuesEvent","repo":{"id":1,"name":"psyomn/gar"},
+                "payload":{"action":"closed","issue":{"id":7,"number":3}}}"#,
+            r#"{"type":"NewEvent","repo":{"id":1,"name":"psyomn/gar"}}"#,
+        ];
+
+        for line in lines.iter() {
+            /* Payloads that haven't been decoded yet still get encoded */
+            let mut ev: Event = Event::from_line(line).unwrap();
+            let mut e = Encoder::new(Vec::new());
+            ev.encode(&mut e).unwrap();
+
+            let mut d = Decoder::new(Cursor::new(e.into_inner()), "test");
+            let back: Event = Event::decode(&mut d).unwrap();
+            ev.decode_payload();
+            assert_eq!(format!("{:?}", back), format!("{:?}", ev));
+        }
+    }
+}
@@ -1,5 +1,479 @@
+use std::fmt;
+
+use chrono::*;
+use regex;
+use regex::Regex;
+
+use error::{GarError, Result};
+use models::date_range;
+use models::event_type::EventType;
+
+/// How the label of a constraint is compared to its value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// `label:value`, the same as `=`
+    Is,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    /// True for `<`, `<=`, `>` and `>=`, which only numbers and dates have
+    pub fn is_ordering(&self) -> bool {
+        matches!(*self, Op::Lt | Op::Le | Op::Gt | Op::Ge)
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: &str = match *self {
+            Op::Is => ":",
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        };
+        f.write_str(s)
+    }
+}
+
+/// What a constraint looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Language,
+    Owner,
+    Actor,
+    Name,
+    Description,
+    Type,
+    CommitComment,
+    Id,
+    Watchers,
+    Stargazers,
+    Forks,
+    OpenIssues,
+    CreatedAt,
+    PushRef,
+    PushHead,
+    PushSize,
+    IssueAction,
+    IssueNumber,
+    WatchAction,
+    DeleteRef,
+    DeleteRefType,
+    GollumPage,
+    GollumAction,
+}
+
+/// The label of each field, as written in a `--where` clause
+const LABELS: &[(&str, Field)] = &[
+    ("language",        Field::Language),
+    ("owner",           Field::Owner),
+    ("actor",           Field::Actor),
+    ("name",            Field::Name),
+    ("description",     Field::Description),
+    ("type",            Field::Type),
+    ("commit_comment",  Field::CommitComment),
+    ("id",              Field::Id),
+    ("watchers",        Field::Watchers),
+    ("stargazers",      Field::Stargazers),
+    ("forks",           Field::Forks),
+    ("open_issues",     Field::OpenIssues),
+    ("created_at",      Field::CreatedAt),
+    ("push.ref",        Field::PushRef),
+    ("push.head",       Field::PushHead),
+    ("push.size",       Field::PushSize),
+    ("issue.action",    Field::IssueAction),
+    ("issue.number",    Field::IssueNumber),
+    ("watch.action",    Field::WatchAction),
+    ("delete.ref",      Field::DeleteRef),
+    ("delete.ref_type", Field::DeleteRefType),
+    ("gollum.page",     Field::GollumPage),
+    ("gollum.action",   Field::GollumAction),
+];
+
+impl Field {
+    /// The field that `label` names. Fails on unknown labels, suggesting the closest known one.
+    pub fn of_label(label: &str) -> Result<Field> {
+        match LABELS.iter().find(|&&(l, _)| l == label) {
+            Some(&(_, field)) => Ok(field),
+            None => {
+                let labels: Vec<&str> = LABELS.iter().map(|&(l, _)| l).collect();
+                Err(GarError::Query(unknown("label", label, &labels)))
+            },
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        LABELS.iter().find(|&&(_, f)| f == *self).map_or("", |&(l, _)| l)
+    }
+
+    /// True for fields found in the payload of events, rather than in their repo
+    pub fn needs_payload(&self) -> bool {
+        matches!(*self, Field::CommitComment | Field::PushRef | Field::PushHead | Field::PushSize |
+                        Field::IssueAction | Field::IssueNumber | Field::WatchAction |
+                        Field::DeleteRef | Field::DeleteRefType | Field::GollumPage |
+                        Field::GollumAction)
+    }
+
+    /// True for fields whose values are text, which regexes can be matched against
+    pub fn is_text(&self) -> bool {
+        !self.is_ordered() && *self != Field::Type
+    }
+
+    /// True for numbers and dates, which can be ordered and given ranges
+    pub fn is_ordered(&self) -> bool {
+        matches!(*self, Field::Id | Field::Watchers | Field::Stargazers | Field::Forks |
+                        Field::OpenIssues | Field::CreatedAt | Field::PushSize | Field::IssueNumber)
+    }
+}
+
+/// The value of a constraint, parsed for its field
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Compared as is: languages, owners, actors, and the text of payloads
+    Text(String),
+    /// Matched case insensitively: names, descriptions and commit comments
+    Regex(Regex),
+    /// The name of an event type, as given by `EventType::to_string`
+    Type(&'static str),
+    /// A range of numbers, both ends included; a single number is a range of its own
+    Numbers(Option<u64>, Option<u64>),
+    /// A range of hours, both ends included. A single date stands for all the hours it spans.
+    Dates(Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+}
+
+/// One constraint of a `--where` clause, checked and compiled once, before any event is looked
+/// at: its label is known, its value parsed, and its regex built.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Constraint {
-    pub label: String,
-    pub value: String,
+    pub field: Field,
+    pub op: Op,
+    pub value: Value,
+}
+
+
+impl Constraint {
+    /// The constraint `label`, `op`, `value`, with the older `+watchers:N` and `-watchers:N`
+    /// turned into `watchers>=N` and `watchers<N` (same for stargazers). Fails on unknown
+    /// labels and event types, malformed values, and orderings of something that isn't a
+    /// number or a date, or of a range.
+    pub fn new(label: &str, op: Op, value: &str) -> Result<Constraint> {
+        let (label, op): (&str, Op) = match (label, op) {
+            ("+watchers", Op::Is) => ("watchers", Op::Ge),
+            ("-watchers", Op::Is) => ("watchers", Op::Lt),
+            ("+stargazers", Op::Is) => ("stargazers", Op::Ge),
+            ("-stargazers", Op::Is) => ("stargazers", Op::Lt),
+            other => other,
+        };
+        let field: Field = Field::of_label(label)?;
+
+        if op.is_ordering() && !field.is_ordered() {
+            return Err(GarError::Query(
+                format!("{} is not a number nor a date, so it can't be compared with {}", label, op)));
+        }
+        if op.is_ordering() && value.contains("..") {
+            return Err(GarError::Query(format!("{}{} takes one value, not a range", label, op)));
+        }
+
+        let value: Value = match field {
+            Field::Name | Field::Description | Field::CommitComment => Value::Regex(regex_of(value, true)?),
+            Field::Type => match EventType::name_of_label(value) {
+                Some(name) => Value::Type(name),
+                None => return Err(GarError::Query(unknown("event type", value, &EventType::labels()))),
+            },
+            Field::CreatedAt => dates_of(value)?,
+            f if f.is_ordered() => numbers_of(label, value)?,
+            _ => Value::Text(value.into()),
+        };
+
+        Ok(Constraint { field, op, value })
+    }
+
+    /// Same as `new`, for a quoted value, which is taken as is: `name`, `description` and
+    /// `commit_comment` look for it as plain text (still ignoring case) rather than as a regex.
+    pub fn new_quoted(label: &str, op: Op, text: &str) -> Result<Constraint> {
+        match Field::of_label(label) {
+            Ok(Field::Name) | Ok(Field::Description) | Ok(Field::CommitComment) =>
+                Constraint::new(label, op, &regex::quote(text)),
+            _ => Constraint::new(label, op, text),
+        }
+    }
+
+    /// The constraint `label`, `op`, `/pattern/`: a regex literal, which is case sensitive unless
+    /// `ignore_case`. Fields that are otherwise compared as is take these too, as in
+    /// `language:/^(c|c\+\+)$/i`.
+    pub fn new_regex(label: &str, op: Op, pattern: &str, ignore_case: bool) -> Result<Constraint> {
+        let field: Field = Field::of_label(label)?;
+
+        if !field.is_text() {
+            return Err(GarError::Query(format!("{} can't be matched against a regex", label)));
+        }
+        if op.is_ordering() {
+            return Err(GarError::Query(format!("a regex can't be compared with {}", op)));
+        }
+
+        Ok(Constraint { field, op, value: Value::Regex(regex_of(pattern, ignore_case)?) })
+    }
+
+    /// True for constraints that look into the payload of events, rather than just their repo
+    pub fn needs_payload(&self) -> bool {
+        self.field.needs_payload()
+    }
+
+    /// True if the constraint holds for a field whose value is `s`
+    pub fn holds_for_text(&self, s: &str) -> bool {
+        self.holds_for_any_text(Some(s))
+    }
+
+    /// True if the constraint holds for a field that has several values, any of which can
+    /// match, such as the comments of the commits of a push
+    pub fn holds_for_any_text<'a, I: IntoIterator<Item = &'a str>>(&self, texts: I) -> bool {
+        let is: bool = texts.into_iter().any(|s| match self.value {
+            Value::Text(ref t) => t == s,
+            Value::Regex(ref re) => re.is_match(s),
+            Value::Type(name) => name == s,
+            _ => false,
+        });
+
+        if self.op == Op::Ne { !is } else { is }
+    }
+
+    /// True if `n` compares to the value as the operator says
+    pub fn holds_for_number(&self, n: u64) -> bool {
+        match self.value {
+            Value::Numbers(from, to) => self.compare(n, from, to),
+            _ => false,
+        }
+    }
+
+    /// True if `d` compares to the value as the operator says, going by the hour `d` falls in:
+    /// `created_at:2015-01` is any time in January 2015, and `created_at>2015-01` is from
+    /// February on.
+    pub fn holds_for_date(&self, d: &DateTime<Utc>) -> bool {
+        match self.value {
+            Value::Dates(from, to) => self.compare(date_range::hour_of(*d), from, to),
+            _ => false,
+        }
+    }
+
+    /// Compare `x` to the range of values from `from` to `to`, both included
+    fn compare<T: PartialOrd + Copy>(&self, x: T, from: Option<T>, to: Option<T>) -> bool {
+        let within: bool = from.is_none_or(|f| x >= f) && to.is_none_or(|t| x <= t);
+
+        match self.op {
+            Op::Is | Op::Eq => within,
+            Op::Ne => !within,
+            Op::Lt => from.is_some_and(|f| x < f),
+            Op::Le => to.is_some_and(|t| x <= t),
+            Op::Gt => to.is_some_and(|t| x > t),
+            Op::Ge => from.is_some_and(|f| x >= f),
+        }
+    }
 }
 
+/// Regex for the `name`, `description` and `commit_comment` constraints (which ignore case), and
+/// for regex literals
+fn regex_of(pattern: &str, ignore_case: bool) -> Result<Regex> {
+    let re_str: String = if ignore_case { format!("(?i){}", pattern) } else { pattern.into() };
+    Regex::new(re_str.as_ref())
+        .map_err(|e| GarError::Query(format!("invalid regex {:?}: {}", pattern, e)))
+}
+
+/// A number, or a range `N..M` of them where either end can be left out
+fn numbers_of(label: &str, value: &str) -> Result<Value> {
+    let number = |s: &str| -> Result<u64> {
+        s.trim().parse::<u64>()
+            .map_err(|_| GarError::Query(format!("{} needs a number, not {:?}", label, value)))
+    };
+
+    let ix: usize = match value.find("..") {
+        Some(ix) => ix,
+        None => { let n = number(value)?; return Ok(Value::Numbers(Some(n), Some(n))) },
+    };
+
+    let (a, b) = (value[..ix].trim(), value[ix + 2..].trim());
+    let from = if a.is_empty() { None } else { Some(number(a)?) };
+    let to = if b.is_empty() { None } else { Some(number(b)?) };
+
+    if let (Some(f), Some(t)) = (from, to) {
+        if f > t {
+            return Err(GarError::Query(format!("range start {} is after its end {}", f, t)));
+        }
+    }
+
+    Ok(Value::Numbers(from, to))
+}
+
+/// Any date that `--range` takes, or a range of them
+fn dates_of(value: &str) -> Result<Value> {
+    let now: DateTime<Utc> = Utc::now();
+
+    if value.contains("..") {
+        let r = date_range::parse_range(value, now)?;
+        Ok(Value::Dates(r.from, r.to))
+    }
+    else {
+        let (f, t) = date_range::parse_span(value, now)?;
+        Ok(Value::Dates(Some(f), Some(t)))
+    }
+}
+
+/// The error message for an unknown `what`, suggesting the closest of `known` when there is one
+/// close enough to be a typo, or listing them all otherwise
+fn unknown(what: &str, given: &str, known: &[&str]) -> String {
+    let closest: Option<(usize, &&str)> = known
+        .iter()
+        .map(|k| (edit_distance(given, k), k))
+        .min_by_key(|&(d, _)| d);
+
+    match closest {
+        Some((d, k)) if d <= 2 && d < given.chars().count() =>
+            format!("unknown {} '{}', did you mean '{}'?", what, given, k),
+        _ => format!("unknown {} '{}'; it can be one of {}", what, given, known.join(", ")),
+    }
+}
+
+/// How many characters must be inserted, removed or replaced to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev: usize = row[0];
+        row[0] = i + 1;
+
+        for j in 0..b.len() {
+            let here: usize = row[j + 1];
+            row[j + 1] = if ca == b[j] { prev } else { 1 + prev.min(row[j]).min(row[j + 1]) };
+            prev = here;
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::*;
+
+    use models::constraint::*;
+
+    fn cons(label: &str, op: Op, value: &str) -> Constraint {
+        Constraint::new(label, op, value).unwrap()
+    }
+
+    fn error_of(label: &str, op: Op, value: &str) -> String {
+        Constraint::new(label, op, value).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_new() -> () {
+        assert_eq!(cons("+watchers", Op::Is, "3"), cons("watchers", Op::Ge, "3"));
+        assert_eq!(cons("-watchers", Op::Is, "3"), cons("watchers", Op::Lt, "3"));
+        assert_eq!(cons("-stargazers", Op::Is, "3"), cons("stargazers", Op::Lt, "3"));
+        assert_eq!(cons("type", Op::Is, "push").value, Value::Type("PushEvent"));
+        assert_eq!(cons("forks", Op::Is, "2..").value, Value::Numbers(Some(2), None));
+        assert_eq!(cons("push.ref", Op::Is, "refs/heads/main").value, Value::Text("refs/heads/main".into()));
+        assert!(Constraint::new_regex("gollum.page", Op::Is, "^home$", true).is_ok());
+        assert!(Constraint::new("language", Op::Ne, "Rust").is_ok());
+
+        assert!(Constraint::new("language", Op::Gt, "Rust").is_err());
+        assert!(Constraint::new("issue.action", Op::Lt, "closed").is_err());
+        assert!(Constraint::new("forks", Op::Gt, "1..2").is_err());
+        assert!(Constraint::new("forks", Op::Is, "many").is_err());
+        assert!(Constraint::new("forks", Op::Is, "4..2").is_err());
+        assert!(Constraint::new("created_at", Op::Is, "last tuesday").is_err());
+        assert!(Constraint::new("name", Op::Is, "(").is_err());
+
+        assert_eq!(error_of("lanuage", Op::Is, "Rust"),
+                   "query error: unknown label 'lanuage', did you mean 'language'?");
+        assert_eq!(error_of("type", Op::Is, "pussh"),
+                   "query error: unknown event type 'pussh', did you mean 'push'?");
+        assert!(error_of("colour", Op::Is, "red").contains("; it can be one of language, owner, "));
+    }
+
+    #[test]
+    fn test_new_regex() -> () {
+        let re = |label: &str, pattern: &str, ignore_case: bool| {
+            Constraint::new_regex(label, Op::Is, pattern, ignore_case).unwrap()
+        };
+
+        assert!(re("name", "^rust-", false).holds_for_text("rust-lang"));
+        assert!(!re("name", "^rust-", false).holds_for_text("Rust-lang"));
+        assert!(re("name", "^rust-", true).holds_for_text("Rust-lang"));
+        assert!(re("language", "^(c|c\\+\\+)$", true).holds_for_text("C++"));
+        assert!(!re("language", "^(c|c\\+\\+)$", true).holds_for_text("C#"));
+
+        assert!(Constraint::new_regex("forks", Op::Is, "1", false).is_err());
+        assert!(Constraint::new_regex("type", Op::Is, "push", false).is_err());
+        assert!(Constraint::new_regex("name", Op::Is, "(", false).is_err());
+    }
+
+    #[test]
+    fn test_new_quoted() -> () {
+        let quoted = |label: &str, text: &str| Constraint::new_quoted(label, Op::Is, text).unwrap();
+
+        assert!(quoted("description", "c++ (fast)").holds_for_text("Is C++ (fast)?"));
+        assert!(!quoted("description", "a.c").holds_for_text("abc"));
+        assert!(quoted("language", "C++").holds_for_text("C++"));
+        assert_eq!(quoted("+watchers", "3"), cons("watchers", Op::Ge, "3"));
+    }
+
+    #[test]
+    fn test_holds() -> () {
+        assert!(cons("language", Op::Is, "Rust").holds_for_text("Rust"));
+        assert!(!cons("language", Op::Is, "Rust").holds_for_text("rust"));
+        assert!(cons("language", Op::Ne, "Rust").holds_for_text("Go"));
+        assert!(cons("name", Op::Is, "^GA").holds_for_text("gar"));
+        assert!(cons("commit_comment", Op::Is, "fix").holds_for_any_text(vec!["typo", "Fixes #3"]));
+        assert!(!cons("commit_comment", Op::Ne, "fix").holds_for_any_text(vec!["typo", "Fixes #3"]));
+        assert!(cons("type", Op::Is, "watch").holds_for_text("WatchEvent"));
+    }
+
+    #[test]
+    fn test_holds_for_number() -> () {
+        let holds = |op: Op, value: &str, n: u64| cons("forks", op, value).holds_for_number(n);
+
+        assert!(holds(Op::Is, "3", 3));
+        assert!(!holds(Op::Eq, "3", 4));
+        assert!(holds(Op::Ne, "3", 4));
+        assert!(holds(Op::Lt, "3", 2) && !holds(Op::Lt, "3", 3));
+        assert!(holds(Op::Le, "3", 3) && !holds(Op::Le, "3", 4));
+        assert!(holds(Op::Gt, "3", 4) && !holds(Op::Gt, "3", 3));
+        assert!(holds(Op::Ge, "3", 3) && !holds(Op::Ge, "3", 2));
+        assert!(holds(Op::Is, "2..4", 2) && holds(Op::Is, "2..4", 4) && !holds(Op::Is, "2..4", 5));
+        assert!(holds(Op::Ne, "2..4", 5) && !holds(Op::Ne, "2..4", 3));
+        assert!(holds(Op::Is, "10..", 1000) && holds(Op::Is, "..10", 0));
+    }
+
+    #[test]
+    fn test_holds_for_date() -> () {
+        let d = Utc.with_ymd_and_hms(2015, 1, 31, 23, 30, 0).unwrap();
+        let holds = |op: Op, value: &str| cons("created_at", op, value).holds_for_date(&d);
+
+        assert!(holds(Op::Is, "2015-01"));
+        assert!(holds(Op::Is, "2015-01-31T23"));
+        assert!(!holds(Op::Ne, "2015"));
+        assert!(holds(Op::Lt, "2015-02") && !holds(Op::Lt, "2015-01"));
+        assert!(holds(Op::Le, "2015-01") && !holds(Op::Le, "2014"));
+        assert!(holds(Op::Gt, "2014") && !holds(Op::Gt, "2015-01-31"));
+        assert!(holds(Op::Ge, "2015-01-31") && !holds(Op::Ge, "2015-02"));
+        assert!(holds(Op::Is, "2015-01-30..2015-02-01") && holds(Op::Is, "..2015-01-31"));
+        assert!(!holds(Op::Is, "2015-02-01.."));
+    }
+
+    #[test]
+    fn test_edit_distance() -> () {
+        assert_eq!(edit_distance("language", "language"), 0);
+        assert_eq!(edit_distance("lanuage", "language"), 1);
+        assert_eq!(edit_distance("forsk", "forks"), 2);
+        assert_eq!(edit_distance("", "id"), 2);
+    }
+}
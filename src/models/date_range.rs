@@ -0,0 +1,361 @@
+use chrono::*;
+
+use error::{GarError, Result};
+
+/// An inclusive range of archive hours. A missing bound leaves that side open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DateRange {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl DateRange {
+    pub fn new(from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<DateRange> {
+        if let (Some(f), Some(t)) = (from, to) {
+            if f > t {
+                return Err(GarError::Parse(format!("range start {} is after its end {}",
+                                   f.format("%Y-%m-%d-%H"), t.format("%Y-%m-%d-%H"))));
+            }
+        }
+        Ok(DateRange { from, to })
+    }
+
+    pub fn contains(&self, d: &DateTime<Utc>) -> bool {
+        self.from.is_none_or(|f| *d >= f) && self.to.is_none_or(|t| *d <= t)
+    }
+
+    /// Every hour in the range, both ends included. An open end stops at `now`; an open start is
+    /// an error, since there is no telling where to begin.
+    pub fn hours(&self, now: DateTime<Utc>) -> Result<Vec<DateTime<Utc>>> {
+        let from = match self.from {
+            Some(f) => f,
+            None => return Err(GarError::Parse("the range needs a start".into())),
+        };
+        let to = self.to.unwrap_or_else(|| hour_of(now));
+
+        let mut v = Vec::new();
+        let mut d = from;
+        while d <= to {
+            v.push(d);
+            d += Duration::hours(1);
+        }
+        Ok(v)
+    }
+}
+
+/// The date arguments shared by `fetch range` and `query`, as given on the command line. Every
+/// argument that is set adds one or more ranges; see `to_ranges_at`.
+#[derive(Debug, Default, Clone)]
+pub struct DateArgs {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub days: Vec<String>,
+    pub months: Vec<String>,
+    pub last: Option<String>,
+    pub ranges: Vec<String>,
+}
+
+impl DateArgs {
+    pub fn is_empty(&self) -> bool {
+        self.from.is_none() && self.to.is_none() && self.days.is_empty() &&
+            self.months.is_empty() && self.last.is_none() && self.ranges.is_empty()
+    }
+
+    /// Turn the arguments into ranges, relative to the current time
+    pub fn to_ranges(&self) -> Result<Vec<DateRange>> {
+        self.to_ranges_at(Utc::now())
+    }
+
+    /// Turn the arguments into ranges, relative to `now`:
+    ///
+    /// * `--from a` and `--to b` make one range, from the start of `a` to the end of `b`; either
+    ///   one may be left out
+    /// * each `--day` and `--month` is a range of that whole day or month
+    /// * `--last` is a range of that many hours or days, ending at the current hour
+    /// * each `--range a..b` is the same as `--from a --to b`
+    pub fn to_ranges_at(&self, now: DateTime<Utc>) -> Result<Vec<DateRange>> {
+        let mut v: Vec<DateRange> = Vec::new();
+
+        if self.from.is_some() || self.to.is_some() {
+            let from = match self.from {
+                Some(ref s) => Some(parse_span(s, now)?.0),
+                None => None,
+            };
+            let to = match self.to {
+                Some(ref s) => Some(parse_span(s, now)?.1),
+                None => None,
+            };
+            v.push(DateRange::new(from, to).map_err(|e| in_context("--from/--to", e))?);
+        }
+
+        for d in &self.days {
+            if !is_day(d) {
+                return Err(GarError::Parse(format!("invalid day {:?}: expected YYYY-mm-dd, today or yesterday", d)));
+            }
+            let (f, t) = parse_span(d, now)?;
+            v.push(DateRange::new(Some(f), Some(t))?);
+        }
+
+        for m in &self.months {
+            if m.splitn(3, '-').count() != 2 {
+                return Err(GarError::Parse(format!("invalid month {:?}: expected YYYY-mm", m)));
+            }
+            let (f, t) = parse_span(m, now)?;
+            v.push(DateRange::new(Some(f), Some(t))?);
+        }
+
+        if let Some(ref l) = self.last {
+            v.push(parse_last(l, now)?);
+        }
+
+        for r in &self.ranges {
+            v.push(parse_range(r, now)?);
+        }
+
+        Ok(v)
+    }
+}
+
+/// True if `d` falls in any of `ranges`. No ranges at all means no constraint.
+pub fn any_contains(ranges: &[DateRange], d: &DateTime<Utc>) -> bool {
+    ranges.is_empty() || ranges.iter().any(|r| r.contains(d))
+}
+
+/// Same as `any_contains`, for any moment rather than an hour (such as when an event happened)
+pub fn any_contains_time(ranges: &[DateRange], d: &DateTime<Utc>) -> bool {
+    any_contains(ranges, &hour_of(*d))
+}
+
+/// Every hour covered by `ranges`, in order and without repeats
+pub fn hours_of(ranges: &[DateRange], now: DateTime<Utc>) -> Result<Vec<DateTime<Utc>>> {
+    let mut v: Vec<DateTime<Utc>> = Vec::new();
+    for r in ranges {
+        v.extend(r.hours(now)?);
+    }
+    v.sort();
+    v.dedup();
+    Ok(v)
+}
+
+/// `a..b`, where either side may be left empty for an open range
+pub fn parse_range(s: &str, now: DateTime<Utc>) -> Result<DateRange> {
+    let ix = match s.find("..") {
+        Some(ix) => ix,
+        None => return Err(GarError::Parse(format!("invalid range {:?}: expected <date>..<date>", s))),
+    };
+    let (a, b) = (s[..ix].trim(), s[ix + 2..].trim());
+
+    let from = if a.is_empty() { None } else { Some(parse_span(a, now)?.0) };
+    let to = if b.is_empty() { None } else { Some(parse_span(b, now)?.1) };
+
+    DateRange::new(from, to).map_err(|e| in_context(&format!("invalid range {:?}", s), e))
+}
+
+/// The longest `--last`, in hours: thirty years, which goes back further than any archive does
+const MAX_LAST_HOURS: i64 = 30 * 366 * 24;
+
+/// `48h` or `7d`: that many hours, ending with the current one, up to `MAX_LAST_HOURS`
+pub fn parse_last(s: &str, now: DateTime<Utc>) -> Result<DateRange> {
+    let err = || GarError::Parse(
+        format!("invalid duration {:?}: expected a number of hours or days, like 48h or 7d", s));
+
+    let s = s.trim();
+    if s.len() < 2 { return Err(err()) }
+
+    let (num, unit) = s.split_at(s.len() - 1);
+    let n: i64 = num.parse().map_err(|_| err())?;
+    let hours: i64 = match unit {
+        "h" => n,
+        "d" => n.checked_mul(24).ok_or_else(err)?,
+        _ => return Err(err()),
+    };
+    if hours < 1 { return Err(err()) }
+    if hours > MAX_LAST_HOURS {
+        return Err(GarError::Parse(
+            format!("invalid duration {:?}: at most {} days can be asked for", s, MAX_LAST_HOURS / 24)));
+    }
+
+    let to = hour_of(now);
+    let from = Duration::try_hours(hours - 1)
+        .and_then(|d| to.checked_sub_signed(d))
+        .ok_or_else(err)?;
+    DateRange::new(Some(from), Some(to))
+}
+
+/// The first and last hour that a single date expression stands for:
+///
+/// * `now` - the current hour
+/// * `today`, `yesterday` - that whole day
+/// * `YYYY-m-d-h` - the archive name format, one hour
+/// * `YYYY-mm-ddTHH`, `YYYY-mm-ddTHH:MM[:SS]`, or a full RFC 3339 date - the hour it falls in
+/// * `YYYY-mm-dd`, `YYYY-mm`, `YYYY` - that whole day, month or year
+pub fn parse_span(s: &str, now: DateTime<Utc>) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let s = s.trim();
+    let err = || GarError::Parse(
+        format!("invalid date {:?}: expected YYYY-mm-dd-h, an ISO date (like 2015-01-01 or \
+                 2015-01-01T15), YYYY-mm, YYYY, now, today or yesterday", s));
+
+    match s {
+        "now" => return Ok((hour_of(now), hour_of(now))),
+        "today" => return Ok(day_span(now.date_naive())),
+        "yesterday" => return Ok(day_span(now.date_naive() - Duration::days(1))),
+        _ => {},
+    }
+
+    if let Ok(d) = DateTime::parse_from_rfc3339(s) {
+        let h = hour_of(d.with_timezone(&Utc));
+        return Ok((h, h));
+    }
+
+    if let Some(ix) = s.find('T') {
+        let date = NaiveDate::parse_from_str(&s[..ix], "%Y-%m-%d").map_err(|_| err())?;
+        let hour: u32 = s[ix + 1..].split(':').next().unwrap_or("").parse().map_err(|_| err())?;
+        let h = at_hour(date, hour).ok_or_else(err)?;
+        return Ok((h, h));
+    }
+
+    let parts: Vec<&str> = s.split('-').collect();
+    let nums: Vec<u32> = parts.iter()
+        .map(|p| p.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()
+        .ok_or_else(err)?;
+
+    match nums.len() {
+        4 => {
+            let date = NaiveDate::from_ymd_opt(nums[0] as i32, nums[1], nums[2]).ok_or_else(err)?;
+            let h = at_hour(date, nums[3]).ok_or_else(err)?;
+            Ok((h, h))
+        },
+        3 => {
+            let date = NaiveDate::from_ymd_opt(nums[0] as i32, nums[1], nums[2]).ok_or_else(err)?;
+            Ok(day_span(date))
+        },
+        2 => {
+            let first = NaiveDate::from_ymd_opt(nums[0] as i32, nums[1], 1).ok_or_else(err)?;
+            let next = if nums[1] == 12 {
+                NaiveDate::from_ymd_opt(nums[0] as i32 + 1, 1, 1)
+            } else {
+                NaiveDate::from_ymd_opt(nums[0] as i32, nums[1] + 1, 1)
+            }.ok_or_else(err)?;
+            Ok((day_span(first).0, day_span(next - Duration::days(1)).1))
+        },
+        1 => {
+            let first = NaiveDate::from_ymd_opt(nums[0] as i32, 1, 1).ok_or_else(err)?;
+            let last = NaiveDate::from_ymd_opt(nums[0] as i32, 12, 31).ok_or_else(err)?;
+            Ok((day_span(first).0, day_span(last).1))
+        },
+        _ => Err(err()),
+    }
+}
+
+/// A date expression that stands for exactly one hour, such as the name of an archive
+pub fn parse_hour(s: &str) -> Result<DateTime<Utc>> {
+    let (f, t) = parse_span(s, Utc::now())?;
+    if f != t {
+        return Err(GarError::Parse(
+            format!("{:?} is more than one hour; expected something like 2015-01-01-15", s)));
+    }
+    Ok(f)
+}
+
+/// Prefix the message of a parse error with where it came from
+fn in_context(what: &str, e: GarError) -> GarError {
+    match e {
+        GarError::Parse(m) => GarError::Parse(format!("{}: {}", what, m)),
+        e => e,
+    }
+}
+
+fn is_day(s: &str) -> bool {
+    s == "today" || s == "yesterday" || (!s.contains('T') && s.split('-').count() == 3)
+}
+
+/// The hour that `d` falls in
+pub fn hour_of(d: DateTime<Utc>) -> DateTime<Utc> {
+    at_hour(d.date_naive(), d.hour()).unwrap_or(d)
+}
+
+fn at_hour(date: NaiveDate, hour: u32) -> Option<DateTime<Utc>> {
+    date.and_hms_opt(hour, 0, 0).map(|d| Utc.from_utc_datetime(&d))
+}
+
+fn day_span(date: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    (at_hour(date, 0).unwrap(), at_hour(date, 23).unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::*;
+    use models::date_range::*;
+
+    fn hour(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_span() -> () {
+        let now = Utc.with_ymd_and_hms(2015, 3, 1, 10, 30, 0).unwrap();
+
+        assert_eq!(parse_span("2013-1-1-5", now).unwrap(), (hour(2013, 1, 1, 5), hour(2013, 1, 1, 5)));
+        assert_eq!(parse_span("2015-01-01T15", now).unwrap(), (hour(2015, 1, 1, 15), hour(2015, 1, 1, 15)));
+        assert_eq!(parse_span("2015-01-01T15:42:00Z", now).unwrap(), (hour(2015, 1, 1, 15), hour(2015, 1, 1, 15)));
+        assert_eq!(parse_span("2015-01-01", now).unwrap(), (hour(2015, 1, 1, 0), hour(2015, 1, 1, 23)));
+        assert_eq!(parse_span("2015-02", now).unwrap(), (hour(2015, 2, 1, 0), hour(2015, 2, 28, 23)));
+        assert_eq!(parse_span("2014-12", now).unwrap(), (hour(2014, 12, 1, 0), hour(2014, 12, 31, 23)));
+        assert_eq!(parse_span("yesterday", now).unwrap(), (hour(2015, 2, 28, 0), hour(2015, 2, 28, 23)));
+
+        assert!(parse_span("2015-13-01", now).is_err());
+        assert!(parse_span("2015-1-1-24", now).is_err());
+        assert!(parse_span("potato", now).is_err());
+    }
+
+    #[test]
+    fn test_date_args_to_ranges() -> () {
+        let now = Utc.with_ymd_and_hms(2015, 3, 1, 10, 30, 0).unwrap();
+
+        let mut args = DateArgs::default();
+        args.from = Some("2015-01-01-22".into());
+        args.to = Some("2015-01-02".into());
+        args.last = Some("2h".into());
+        args.ranges = vec!["2014-12-31..".into()];
+
+        let ranges = args.to_ranges_at(now).unwrap();
+        assert_eq!(ranges, vec![
+            DateRange { from: Some(hour(2015, 1, 1, 22)), to: Some(hour(2015, 1, 2, 23)) },
+            DateRange { from: Some(hour(2015, 3, 1, 9)), to: Some(hour(2015, 3, 1, 10)) },
+            DateRange { from: Some(hour(2014, 12, 31, 0)), to: None },
+        ]);
+        assert_eq!(ranges[0].hours(now).unwrap().len(), 26);
+
+        args = DateArgs::default();
+        args.from = Some("2015-01-02".into());
+        args.to = Some("2015-01-01".into());
+        assert!(args.to_ranges_at(now).is_err());
+    }
+
+    #[test]
+    fn test_parse_last() -> () {
+        let now = Utc.with_ymd_and_hms(2015, 3, 1, 10, 30, 0).unwrap();
+
+        assert_eq!(parse_last("1d", now).unwrap(),
+                   DateRange { from: Some(hour(2015, 2, 28, 11)), to: Some(hour(2015, 3, 1, 10)) });
+        assert!(parse_last("0h", now).is_err());
+        assert!(parse_last("7w", now).is_err());
+
+        /* Oversized durations are errors, not overflows */
+        assert!(parse_last("9999999999d", now).is_err());
+        assert!(parse_last("999999999999999d", now).is_err());
+        assert!(parse_last("9223372036854775807d", now).is_err());
+        assert!(parse_last("9223372036854775807h", now).is_err());
+    }
+
+    #[test]
+    fn test_any_contains_time() -> () {
+        let ranges = vec![DateRange { from: Some(hour(2015, 1, 1, 10)), to: Some(hour(2015, 1, 1, 12)) }];
+
+        assert!(any_contains_time(&ranges, &Utc.with_ymd_and_hms(2015, 1, 1, 12, 59, 59).unwrap()));
+        assert!(!any_contains_time(&ranges, &Utc.with_ymd_and_hms(2015, 1, 1, 13, 0, 0).unwrap()));
+        assert!(!any_contains_time(&ranges, &Utc.with_ymd_and_hms(2015, 1, 1, 9, 59, 0).unwrap()));
+        assert!(any_contains_time(&[], &Utc.with_ymd_and_hms(2015, 1, 1, 9, 59, 0).unwrap()));
+    }
+}
@@ -1,12 +1,18 @@
+use std::cell::OnceCell;
 use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 
-use regex::Regex;
-use rustc_serialize::json::Json;
+use rustc_serialize::json::{Json, Object};
 
+use error::Result;
 use models::owner;
-use models::reader::lines_of;
-use models::constraint::Constraint;
+use models::reader::{archive_lines_of, ArchiveLines};
+use models::compact;
+use models::compact::{Compact, Decoder, Encoder};
+use models::constraint::{Constraint, Field, Op};
 use models::event_type::EventType;
 use models::json_helpers::JsonHelper;
 
@@ -14,7 +20,13 @@ use models::payloads::*;
 
 use chrono::*;
 
-#[derive(Debug)]
+/// The labels of `to_btree_with_all_features`, which are also what `to_btree_with_features_of`
+/// can be asked for
+pub const FEATURES: &[&str] = &[
+    "id", "name", "description", "language", "has_issues", "owner", "url", "watchers",
+    "stargazers", "forks", "open_issues", "actor", "org", "public", "event_type", "created_at",
+];
+
 pub struct Event {
     gh_id: u64,
     name: String,
@@ -27,8 +39,41 @@ pub struct Event {
     stargazers: u64,
     forks: u64,
     open_issues: u64,
+    actor: String,
+    org: String,
+    public: bool,
     event_type: Option<EventType>,
     created_at: Option<DateTime<Utc>>,
+    /// The payload as it was in the archive, while it hasn't been parsed into `event_type` yet
+    /// (see `from_line` and `decode_payload`)
+    raw_payload: Option<String>,
+    /// `raw_payload` parsed into the event type, the first time a constraint asks for it, so that
+    /// several constraints on the payload of one event parse it once
+    decoded: OnceCell<Option<EventType>>,
+}
+
+/// Shows the event type with its payload decoded, and none of what is kept around to decode it
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Event")
+            .field("gh_id", &self.gh_id)
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .field("language", &self.language)
+            .field("has_issues", &self.has_issues)
+            .field("owner", &self.owner)
+            .field("url", &self.url)
+            .field("watchers", &self.watchers)
+            .field("stargazers", &self.stargazers)
+            .field("forks", &self.forks)
+            .field("open_issues", &self.open_issues)
+            .field("actor", &self.actor)
+            .field("org", &self.org)
+            .field("public", &self.public)
+            .field("event_type", &self.typed_event_type())
+            .field("created_at", &self.created_at)
+            .finish()
+    }
 }
 
 /// Models a repo event, in the file obtained from githubarchive.
@@ -46,8 +91,13 @@ impl Event {
             stargazers: 0,
             forks: 0,
             open_issues: 0,
+            actor: "default".into(),
+            org: "default".into(),
+            public: false,
             event_type: None,
             created_at: None,
+            raw_payload: None,
+            decoded: OnceCell::new(),
         }
     }
 
@@ -67,143 +117,255 @@ impl Event {
         self.created_at = Some(e);
     }
 
-    /// Provided a list of constraints, this will return true only if ALL of them are satisfied
+    /// Github id of the repository
+    pub fn gh_id(&self) -> u64 {
+        self.gh_id
+    }
+
+    /// Name of the repository, without its owner
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn has_issues(&self) -> bool {
+        self.has_issues
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn watchers(&self) -> u64 {
+        self.watchers
+    }
+
+    pub fn stargazers(&self) -> u64 {
+        self.stargazers
+    }
+
+    pub fn forks(&self) -> u64 {
+        self.forks
+    }
+
+    pub fn open_issues(&self) -> u64 {
+        self.open_issues
+    }
+
+    /// Login of whoever caused the event
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    pub fn org(&self) -> &str {
+        &self.org
+    }
+
+    pub fn public(&self) -> bool {
+        self.public
+    }
+
+    pub fn owner_nick(&self) -> &str {
+        self.owner.get_nick()
+    }
+
+    pub fn event_type(&self) -> Option<&EventType> {
+        self.event_type.as_ref()
+    }
+
+    pub fn created_at(&self) -> Option<&DateTime<Utc>> {
+        self.created_at.as_ref()
+    }
+
+    /// Parse the payload that `from_line` put aside into `event_type`. Queries call this only
+    /// when something they look at (a constraint, or the output) needs the payload.
+    pub fn decode_payload(&mut self) -> () {
+        self.decoded_event_type();
+        if let Some(Some(t)) = self.decoded.take() {
+            self.event_type = Some(t);
+        }
+        self.raw_payload = None;
+    }
+
+    /// The event type with the payload put aside by `from_line` parsed in, leaving the event as
+    /// it is. `None` when there is no such payload. The payload is parsed on the first call only.
+    fn decoded_event_type(&self) -> &Option<EventType> {
+        self.decoded.get_or_init(|| {
+            let raw: &String = self.raw_payload.as_ref()?;
+            let payload: Option<Json> = Json::from_str(raw).ok();
+
+            self.event_type.as_ref()?.with_payload(payload.as_ref())
+        })
+    }
+
+    /// The event type, with its payload whether or not `decode_payload` was called
+    fn typed_event_type(&self) -> Option<&EventType> {
+        self.decoded_event_type().as_ref().or(self.event_type.as_ref())
+    }
+
+    /// Provided a list of constraints, this will return true only if ALL of them are satisfied.
     pub fn satisfies_constraints(&self, v: &Vec<Constraint>) -> bool {
-        let mut b = true;
-        for cons in v.iter() {
-            if cons.label == "language" {
-                /* Example: language:Rust */
-                b &= cons.value == self.language;
-            }
-            if cons.label == "owner" {
-                /* Example: owner:psyomn */
-                b &= cons.value == *self.owner.get_nick();
-            }
-            if cons.label == "name" {
-                /* Example: name:wayland
-                 * This will perform a regex match against the name of the repo
-                 */
-                let re_str: String = format!("(?i){}", cons.value);
-                let re: Regex = Regex::new(re_str.as_ref()).unwrap();
-                b &= re.is_match(self.name.as_ref());
-            }
-            if cons.label == "description" {
-                /* This does a wor dmatch against the description given to the event's repo */
-                let re_str: String = format!("(?i){}", cons.value);
-                let re: Regex = Regex::new(re_str.as_ref()).unwrap();
-                b &= re.is_match(self.description.as_ref());
-            }
-            if cons.label == "+watchers" {
-                /* TODO: parsing to int each time - this might not be good? */
-                let num: u64 = cons.value.parse::<u64>().ok().unwrap();
-                b &= num <= self.watchers;
-            }
-            if cons.label == "-watchers" {
-                /* TODO: parsing to int each time - this might not be good? */
-                let num: u64 = cons.value.parse::<u64>().ok().unwrap();
-                b &= num > self.watchers;
-            }
-            if cons.label == "+stargazers" {
-                /* TODO: parsing to int each time - this might not be good? */
-                let num: u64 = cons.value.parse::<u64>().ok().unwrap();
-                b &= num <= self.stargazers;
-            }
-            if cons.label == "-stargazers" {
-                /* TODO: parsing to int each time - this might not be good? */
-                let num: u64 = cons.value.parse::<u64>().ok().unwrap();
-                b &= num < self.stargazers;
-            }
-            if cons.label == "type" {
-                let etype: &EventType = match self.event_type {
-                    Some(ref v) => v,
-                    None => continue,
-                };
-
-                b &= match cons.value.as_ref() {
-                    "create"                      => match etype { &EventType::Create                   => true, _ => false },
-                    "commit_comment"              => match etype { &EventType::CommitComment            => true, _ => false },
-                    "delete"                      => match etype { &EventType::Delete(..)               => true, _ => false },
-                    "deployment"                  => match etype { &EventType::Deployment               => true, _ => false },
-                    "deployment_status"           => match etype { &EventType::DeploymentStatus         => true, _ => false },
-                    "download"                    => match etype { &EventType::Download                 => true, _ => false },
-                    "follow"                      => match etype { &EventType::Follow                   => true, _ => false },
-                    "fork"                        => match etype { &EventType::Fork                     => true, _ => false },
-                    "fork_apply"                  => match etype { &EventType::ForkApply                => true, _ => false },
-                    "gist"                        => match etype { &EventType::Gist                     => true, _ => false },
-                    "gollum"                      => match etype { &EventType::Gollum(..)               => true, _ => false },
-                    "issue_comment"               => match etype { &EventType::IssueComment(..)         => true, _ => false },
-                    "issues"                      => match etype { &EventType::Issues(..)               => true, _ => false },
-                    "member"                      => match etype { &EventType::Member                   => true, _ => false },
-                    "membership"                  => match etype { &EventType::Membership               => true, _ => false },
-                    "page_build"                  => match etype { &EventType::PageBuild                => true, _ => false },
-                    "public"                      => match etype { &EventType::Public                   => true, _ => false },
-                    "pull_request"                => match etype { &EventType::PullRequest              => true, _ => false },
-                    "pull_request_review_comment" => match etype { &EventType::PullRequestReviewComment => true, _ => false },
-                    "push"                        => match etype { &EventType::Push(..)                 => true, _ => false },
-                    "release"                     => match etype { &EventType::Release                  => true, _ => false },
-                    "repository"                  => match etype { &EventType::Repository               => true, _ => false },
-                    "status"                      => match etype { &EventType::Status                   => true, _ => false },
-                    "team_add"                    => match etype { &EventType::TeamAdd                  => true, _ => false },
-                    "watch"                       => match etype { &EventType::Watch(..)                => true, _ => false },
-                    _                             => true, /* Ignore erroneous input */
-                }
-            }
-            if cons.label == "commit_comment" {
-                /* Prereq: for this match to happen, we want to make sure that we ahve a Push
-                 * Event, as the match depends on the respective payload */
-
-                let etype: &EventType = match self.event_type {
-                    Some(ref v) => v,
-                    None => continue,
-                };
-
-                match etype {
-                    &EventType::Push(ref payload) => {
-                        if let Some(ref payload) = *payload {
-                            /* Does the commit comment contain some particular text? */
-                            let txt: &str = cons.value.as_ref();
-                            b &= payload.sha_elements_contain_text_of(txt);
-                        }
-                    },
-                    _ => continue,
-                }
+        v.iter().all(|cons| self.satisfies(cons))
+    }
 
-            }
+    /// True if the event satisfies `cons` (see `Expr` for combining constraints)
+    pub fn satisfies(&self, cons: &Constraint) -> bool {
+        match cons.field {
+            /* Example: language:Rust */
+            Field::Language => cons.holds_for_text(&self.language),
+            /* Example: owner:psyomn */
+            Field::Owner => cons.holds_for_text(self.owner.get_nick()),
+            /* Example: actor:psyomn */
+            Field::Actor => cons.holds_for_text(&self.actor),
+            /* Example: name:wayland
+             * This will perform a regex match against the name of the repo
+             */
+            Field::Name => cons.holds_for_text(&self.name),
+            /* This does a wor dmatch against the description given to the event's repo */
+            Field::Description => cons.holds_for_text(&self.description),
+            /* Example: watchers>=100, forks:10..20 */
+            Field::Id => cons.holds_for_number(self.gh_id),
+            Field::Watchers => cons.holds_for_number(self.watchers),
+            Field::Stargazers => cons.holds_for_number(self.stargazers),
+            Field::Forks => cons.holds_for_number(self.forks),
+            Field::OpenIssues => cons.holds_for_number(self.open_issues),
+            /* Example: created_at>=2015-01-15 */
+            Field::CreatedAt => self.created_at.as_ref().is_some_and(|d| cons.holds_for_date(d)),
+            /* Events of a type gar doesn't know are of none of the types it does */
+            Field::Type => match self.event_type {
+                Some(ref t) => cons.holds_for_text(&t.to_string()),
+                None => cons.op == Op::Ne,
+            },
+            /* Example: commit_comment:fix, issue.action:closed, push.size>=10, gollum.page:Home */
+            _ => Event::payload_satisfies(self.typed_event_type(), cons),
         }
-        b
-    }
-
-    /// Given a path to a json.gz file, that file is read, and each line is parsed to a Event
-    /// object.
-    pub fn from_path(p: PathBuf) -> Vec<Event> {
-        let v: Vec<String> = lines_of(p);
-        let mut res: Vec<Event> = Vec::new();
-
-        for line in v.into_iter() {
-            let json_line: Json = match Json::from_str(line.as_ref()) {
-                Ok(v)  => v,
-                Err(e) => {
-                    ::print_red(format!("Could not parse anything given:\n{}", line).as_ref());
-                    ::print_red(format!("Err: {}", e).as_ref());
-                    continue;
-                },
-            };
+    }
 
-            if let Some(v) = Event::from_json(Some(&json_line)) {
-                res.push(v);
-            };
+    /// True if the typed payload of an event of type `t` satisfies `cons`, a constraint on a
+    /// field of that payload. Events of another type, or without a payload, never do.
+    fn payload_satisfies(t: Option<&EventType>, cons: &Constraint) -> bool {
+        match (cons.field, t) {
+            /* Does the comment of any of the commits contain some particular text? */
+            (Field::CommitComment, Some(&EventType::Push(Some(ref p)))) =>
+                cons.holds_for_any_text(p.get_shas().iter().map(|e| e.get_comment().as_str())),
+            (Field::PushRef, Some(&EventType::Push(Some(ref p)))) => cons.holds_for_text(p.get_ref()),
+            (Field::PushHead, Some(&EventType::Push(Some(ref p)))) => cons.holds_for_text(p.get_head()),
+            (Field::PushSize, Some(&EventType::Push(Some(ref p)))) => cons.holds_for_number(p.get_size()),
+            (Field::IssueAction, Some(&EventType::Issues(Some(ref p)))) => cons.holds_for_text(p.get_action()),
+            (Field::IssueNumber, Some(&EventType::Issues(Some(ref p)))) => cons.holds_for_number(p.get_number()),
+            (Field::WatchAction, Some(&EventType::Watch(Some(ref p)))) => cons.holds_for_text(p.get_action()),
+            (Field::DeleteRef, Some(&EventType::Delete(Some(ref p)))) => cons.holds_for_text(p.get_ref_tag()),
+            (Field::DeleteRefType, Some(&EventType::Delete(Some(ref p)))) => cons.holds_for_text(p.get_ref_tag_type()),
+            /* A wiki edit can touch several pages: any of them will do */
+            (Field::GollumPage, Some(&EventType::Gollum(Some(ref p)))) =>
+                cons.holds_for_any_text(p.get_pages().iter().map(|e| e.get_page_name().as_str())),
+            (Field::GollumAction, Some(&EventType::Gollum(Some(ref p)))) =>
+                cons.holds_for_any_text(p.get_pages().iter().map(|e| e.get_action().as_str())),
+            _ => false,
         }
+    }
+
+    /// Given a path to an archive (json.gz, or see `reader::Compression` for the others), that
+    /// file is read, and each line is parsed to a Event object. Lines that are not json are
+    /// skipped (`gar verify` reports them). When `gar compact` has made an up to date compacted
+    /// version of the file, that is read instead.
+    pub fn from_path(p: PathBuf) -> Result<Vec<Event>> {
+        Event::iter_path(p).collect()
+    }
 
-        res
+    /// The events of the archive at `p`, parsed one line at a time as the iterator is consumed,
+    /// so that memory use doesn't grow with the size of the archive. A file that can't be opened,
+    /// or that turns out to be truncated or corrupt, gives an `Err` after the events that could
+    /// be read. Like `from_path`, this reads the compacted version of the file when there is an
+    /// up to date one.
+    pub fn iter_path(p: PathBuf) -> impl Iterator<Item = Result<Event>> {
+        let events: Box<dyn Iterator<Item = Result<Event>>> = match compact::open_fresh(&p) {
+            Some(events) => Box::new(events),
+            None => Box::new(Event::iter_json_path(p)),
+        };
+        events
     }
 
-    /// Given a json string, try to evaluate it into a repo
+    /// Same as `iter_path`, but always parsing the json archive itself
+    pub fn iter_json_path(p: PathBuf) -> impl Iterator<Item = Result<Event>> {
+        Event::events_of_lines(archive_lines_of(p))
+    }
+
+    /// Same as `iter_path`, for an archive that is held in memory
+    pub fn iter_bytes<'a>(bytes: &'a [u8], origin: &str) -> impl Iterator<Item = Result<Event>> + 'a {
+        Event::iter_reader(bytes, origin)
+    }
+
+    /// Same as `iter_path`, for an archive that is read from `r` (such as standard input)
+    pub fn iter_reader<'a, R: Read + 'a>(r: R, origin: &str) -> impl Iterator<Item = Result<Event>> + 'a {
+        Event::events_of_lines(ArchiveLines::new(r, origin))
+    }
+
+    /// The events of `lines`, or just the error if there are none to be had
+    fn events_of_lines<'a>(lines: Result<ArchiveLines<'a>>) -> impl Iterator<Item = Result<Event>> + 'a {
+        let (lines, err) = match lines {
+            Ok(lines) => (Some(lines), None),
+            Err(e) => (None, Some(Err(e))),
+        };
+
+        err.into_iter().chain(Event::events_of(lines.into_iter().flatten()))
+    }
+
+    fn events_of<I: Iterator<Item = Result<String>>>(lines: I) -> impl Iterator<Item = Result<Event>> {
+        lines.filter_map(|line| match line {
+            Ok(line) => Event::from_line(&line).map(Ok),
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    /// A single line of an archive; `None` if it isn't json, or not an event we understand.
+    /// Payloads make up most of an archive, but few queries look at them, so the payload is only
+    /// put aside here, unparsed: see `decode_payload`.
+    pub fn from_line(line: &str) -> Option<Event> {
+        let (start, end) = match JsonHelper::member_span(line, "payload") {
+            Some(span) => span,
+            None => return Event::from_json(Some(&Json::from_str(line).ok()?)),
+        };
+
+        let rest: String = format!("{}null{}", &line[..start], &line[end..]);
+        let mut ev: Event = Event::from_json(Some(&Json::from_str(&rest).ok()?))?;
+
+        if ev.event_type.as_ref().is_some_and(|t| t.has_payload()) {
+            ev.raw_payload = Some(line[start..end].into());
+        }
+
+        Some(ev)
+    }
+
+    /// Given a json string, try to evaluate it into a repo. Lines from both eras of githubarchive
+    /// are understood: the legacy timeline format (up to the end of 2014) which carries a full
+    /// `repository` object, and the Events API format (2015 onwards) which carries `repo`,
+    /// `actor` and `org` objects instead. Which one is used is decided per line.
     pub fn from_json(json: Option<&Json>) -> Option<Event> {
         if json.is_none() { return None }
         if !json.unwrap().is_object() { return None }
 
         let obj = json.unwrap().as_object().unwrap();
 
+        let mut repo: Event = Event::new();
+
+        if let Some(legacy_repo) = obj.get("repository").and_then(|v| v.as_object()) {
+            repo.fill_from_legacy(obj, legacy_repo);
+        }
+        else if let Some(api_repo) = obj.get("repo").and_then(|v| v.as_object()) {
+            repo.fill_from_events_api(obj, api_repo);
+        }
+        else {
+            return None;
+        }
+
         let created_at: Option<DateTime<Utc>> = match obj.get("created_at") {
             Some(v) => {
                 match *v {
@@ -220,12 +382,67 @@ impl Event {
             None => None
         };
 
-        let repo = match obj.get("repository") {
-            None => return None,
-            Some(v) => v.as_object().unwrap(),
+        repo.public = JsonHelper::boolean_or_false(obj.get("public"));
+        repo.event_type = Event::event_type_of(obj);
+        repo.created_at = created_at;
+
+        Some(repo)
+    }
+
+    /// Legacy timeline format: the repository is fully described inline, and the actor is a plain
+    /// login string.
+    fn fill_from_legacy(&mut self, obj: &Object, repo: &Object) {
+        self.set_owner_nick(JsonHelper::string_or_empty(repo.get("owner")));
+
+        self.gh_id = JsonHelper::number_or_zero(repo.get("id"));
+        self.url = JsonHelper::string_or_empty(repo.get("url"));
+        self.description = JsonHelper::string_or_empty(repo.get("description"));
+        self.name = JsonHelper::string_or_empty(repo.get("name"));
+        self.has_issues = JsonHelper::boolean_or_false(repo.get("has_issues"));
+        self.language = JsonHelper::string_or_empty(repo.get("language"));
+        self.stargazers = JsonHelper::number_or_zero(repo.get("stargazers"));
+        self.watchers = JsonHelper::number_or_zero(repo.get("watchers"));
+        self.forks = JsonHelper::number_or_zero(repo.get("forks"));
+        self.open_issues = JsonHelper::number_or_zero(repo.get("open_issues"));
+        self.actor = JsonHelper::string_or_empty(obj.get("actor"));
+        self.org = JsonHelper::string_or_empty(repo.get("organization"));
+    }
+
+    /// Events API format: the repo only carries its id and "owner/name", and the actor and
+    /// organization are objects of their own. Repository statistics (language, watchers, ...) are
+    /// not part of this format, so those are left empty.
+    fn fill_from_events_api(&mut self, obj: &Object, repo: &Object) {
+        let full_name: String = JsonHelper::string_or_empty(repo.get("name"));
+
+        let (owner_name, name): (String, String) = match full_name.find('/') {
+            Some(ix) => (full_name[..ix].into(), full_name[ix + 1..].into()),
+            None => ("".into(), full_name.clone()),
         };
 
-        let event: Option<EventType> = match obj.get("type") {
+        let actor: String = obj.get("actor")
+            .and_then(|v| v.as_object())
+            .map(|a| JsonHelper::string_or_empty(a.get("login")))
+            .unwrap_or("".into());
+
+        let org: String = obj.get("org")
+            .and_then(|v| v.as_object())
+            .map(|o| JsonHelper::string_or_empty(o.get("login")))
+            .unwrap_or("".into());
+
+        self.set_owner_nick(owner_name);
+
+        self.gh_id = JsonHelper::number_or_zero(repo.get("id"));
+        self.url = format!("https://github.com/{}", full_name);
+        self.name = name;
+        self.actor = actor;
+        self.org = org;
+        self.description = "".into();
+        self.language = "".into();
+    }
+
+    /// The type of the event, along with its parsed payload where we support one.
+    fn event_type_of(obj: &Object) -> Option<EventType> {
+        match obj.get("type") {
             None => None,
             Some(v) => match *v {
                 Json::String(ref s) => {
@@ -260,42 +477,11 @@ impl Event {
                 },
                 _ => None,
             },
-        };
-
-        let gh_id = JsonHelper::number_or_zero(repo.get("id"));
-        let name: String = JsonHelper::string_or_empty(repo.get("name"));
-        let url: String = JsonHelper::string_or_empty(repo.get("url"));
-        let desc: String = JsonHelper::string_or_empty(repo.get("description"));
-        let owner_name: String = JsonHelper::string_or_empty(repo.get("owner"));
-        let issues_present: bool = JsonHelper::boolean_or_false(repo.get("has_issues"));
-        let language: String = JsonHelper::string_or_empty(repo.get("language"));
-        let num_stargazers: u64 = JsonHelper::number_or_zero(repo.get("stargazers"));
-        let watchers: u64 = JsonHelper::number_or_zero(repo.get("watchers"));
-        let forks: u64 = JsonHelper::number_or_zero(repo.get("forks"));
-        let open_issues: u64 = JsonHelper::number_or_zero(repo.get("open_issues"));
-
-        let mut repo: Event = Event::new();
-
-        repo.set_owner_nick(owner_name);
-
-        repo.gh_id = gh_id;
-        repo.url = url;
-        repo.description = desc;
-        repo.name = name;
-        repo.has_issues = issues_present;
-        repo.language = language;
-        repo.event_type = event;
-        repo.stargazers = num_stargazers;
-        repo.watchers = watchers;
-        repo.forks = forks;
-        repo.created_at = created_at;
-        repo.open_issues = open_issues;
-
-        Some(repo)
+        }
     }
 
-    /// Gives a flat json hash with labels and values.
-    pub fn to_btree_with_features_of(&self, f: Vec<String>) -> BTreeMap<String, String> {
+    /// Gives a flat json hash with labels and values, for the labels in `f` (see `FEATURES`).
+    pub fn to_btree_with_features_of(&self, f: &Vec<String>) -> BTreeMap<String, String> {
         let mut map: BTreeMap<String, String> = BTreeMap::new();
 
         let id_label: String = "id".into();
@@ -308,44 +494,56 @@ impl Event {
         let watchers: String = "watchers".into();
         let stargazers: String = "stargazers".into();
         let forks: String = "forks".into();
-        let open_issues: String = "forks".into();
+        let open_issues: String = "open_issues".into();
+        let actor: String = "actor".into();
+        let org: String = "org".into();
+        let public: String = "public".into();
         let event_type: String = "event_type".into();
         let created_at: String = "created_at".into();
 
-        if ::vec_contains(&f, &id_label) {
+        if ::vec_contains(f, &id_label) {
             map.insert(id_label, self.gh_id.to_string());
         }
-        if ::vec_contains(&f, &name_label) {
+        if ::vec_contains(f, &name_label) {
             map.insert(name_label, self.name.clone());
         }
-        if ::vec_contains(&f, &desc) {
+        if ::vec_contains(f, &desc) {
             map.insert(desc, self.description.clone());
         }
-        if ::vec_contains(&f, &lang) {
+        if ::vec_contains(f, &lang) {
             map.insert(lang, self.language.clone());
         }
-        if ::vec_contains(&f, &has_issues) {
+        if ::vec_contains(f, &has_issues) {
             map.insert(has_issues, self.has_issues.to_string());
         }
-        if ::vec_contains(&f, &owner) {
+        if ::vec_contains(f, &owner) {
             map.insert(owner, self.owner.get_nick().clone());
         }
-        if ::vec_contains(&f, &url) {
+        if ::vec_contains(f, &url) {
             map.insert(url, self.url.clone());
         }
-        if ::vec_contains(&f, &watchers) {
+        if ::vec_contains(f, &watchers) {
             map.insert(watchers, self.watchers.to_string());
         }
-        if ::vec_contains(&f, &stargazers) {
+        if ::vec_contains(f, &stargazers) {
             map.insert(stargazers, self.stargazers.to_string());
         }
-        if ::vec_contains(&f, &forks) {
+        if ::vec_contains(f, &forks) {
             map.insert(forks, self.forks.to_string());
         }
-        if ::vec_contains(&f, &open_issues) {
+        if ::vec_contains(f, &open_issues) {
             map.insert(open_issues, self.open_issues.to_string());
         }
-        if ::vec_contains(&f, &event_type) {
+        if ::vec_contains(f, &actor) {
+            map.insert(actor, self.actor.clone());
+        }
+        if ::vec_contains(f, &org) {
+            map.insert(org, self.org.clone());
+        }
+        if ::vec_contains(f, &public) {
+            map.insert(public, self.public.to_string());
+        }
+        if ::vec_contains(f, &event_type) {
             if let Some(ref etype) = self.event_type {
                 map.insert(event_type, etype.to_string());
             }
@@ -353,7 +551,7 @@ impl Event {
                 map.insert(event_type, "null".into());
             }
         }
-        if ::vec_contains(&f, &created_at) {
+        if ::vec_contains(f, &created_at) {
             if let Some(date) = self.created_at {
                 map.insert(created_at, date.to_rfc3339());
             }
@@ -378,6 +576,10 @@ impl Event {
         map.insert("watchers".into(), self.watchers.to_string());
         map.insert("stargazers".into(), self.stargazers.to_string());
         map.insert("forks".into(), self.forks.to_string());
+        map.insert("open_issues".into(), self.open_issues.to_string());
+        map.insert("actor".into(), self.actor.clone());
+        map.insert("org".into(), self.org.clone());
+        map.insert("public".into(), self.public.to_string());
 
         if let Some(ref etype) = self.event_type {
             map.insert("event_type".into(), etype.to_string());
@@ -389,3 +591,229 @@ impl Event {
         map
     }
 }
+
+impl Compact for Event {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.u64(self.gh_id)?;
+        e.str(&self.name)?;
+        e.str(&self.description)?;
+        e.str(&self.language)?;
+        e.bool(self.has_issues)?;
+        self.owner.encode(e)?;
+        e.str(&self.url)?;
+        e.u64(self.watchers)?;
+        e.u64(self.stargazers)?;
+        e.u64(self.forks)?;
+        e.u64(self.open_issues)?;
+        e.str(&self.actor)?;
+        e.str(&self.org)?;
+        e.bool(self.public)?;
+
+        /* Compacted archives always hold the parsed payload */
+        let decoded: &Option<EventType> = self.decoded_event_type();
+        e.option(if decoded.is_some() { decoded } else { &self.event_type })?;
+
+        /* Seconds and nanoseconds since the epoch */
+        match self.created_at {
+            Some(date) => {
+                e.u8(1)?;
+                e.i64(date.timestamp())?;
+                e.u64(date.timestamp_subsec_nanos() as u64)
+            },
+            None => e.u8(0),
+        }
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<Event> {
+        let mut ev = Event {
+            gh_id: d.u64()?,
+            name: d.string()?,
+            description: d.string()?,
+            language: d.string()?,
+            has_issues: d.bool()?,
+            owner: owner::Owner::decode(d)?,
+            url: d.string()?,
+            watchers: d.u64()?,
+            stargazers: d.u64()?,
+            forks: d.u64()?,
+            open_issues: d.u64()?,
+            actor: d.string()?,
+            org: d.string()?,
+            public: d.bool()?,
+            event_type: d.option()?,
+            created_at: None,
+            raw_payload: None,
+            decoded: OnceCell::new(),
+        };
+
+        ev.created_at = match d.u8()? {
+            0 => None,
+            1 => {
+                let (secs, nanos) = (d.i64()?, d.u64()?);
+                match DateTime::from_timestamp(secs, nanos as u32) {
+                    Some(date) => Some(date),
+                    None => return Err(d.corrupt("bad date")),
+                }
+            },
+            _ => return Err(d.corrupt("bad option tag")),
+        };
+
+        Ok(ev)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use rustc_serialize::json::Json;
+    use std::io::Write;
+    use models::constraint::{Constraint, Op};
+    use models::event::{Event, FEATURES};
+
+    #[test]
+    fn test_from_json_legacy_schema() -> () {
+        let line = r#"{"created_at":"2013-01-01T01:00:00-08:00","public":true,"type":"WatchEvent",
+                       "actor":"jon","payload":{"action":"started"},
+                       "repository":{"id":724712,"name":"rust","owner":"mozilla","language":"Rust",
+                                     "url":"https://github.com/mozilla/rust","watchers":10}}"#;
+        let json = Json::from_str(line).unwrap();
+        let ev = Event::from_json(Some(&json)).unwrap();
+        let map = ev.to_btree_with_all_features();
+
+        assert_eq!(map["id"], "724712");
+        assert_eq!(map["name"], "rust");
+        assert_eq!(map["owner"], "mozilla");
+        assert_eq!(map["actor"], "jon");
+        assert_eq!(map["language"], "Rust");
+        assert_eq!(map["event_type"], "WatchEvent");
+        assert_eq!(map["created_at"], "2013-01-01T09:00:00+00:00");
+    }
+
+    #[test]
+    fn test_from_json_events_api_schema() -> () {
+        let line = r#"{"id":"2489651045","type":"PushEvent","public":true,
+                       "created_at":"2015-01-01T15:00:00Z",
+                       "actor":{"id":665991,"login":"jon"},
+                       "repo":{"id":28688495,"name":"psyomn/gar",
+                               "url":"https://api.github.com/repos/psyomn/gar"},
+                       "org":{"id":1,"login":"someorg"},
+                       "payload":{"size":1,"ref":"refs/heads/master","head":"abc",
+                                  "commits":[{"sha":"abc","message":"Fix the thing",
+                                              "author":{"email":"a@b.c","name":"jon"},
+                                              "distinct":true}]}}"#;
+        let json = Json::from_str(line).unwrap();
+        let ev = Event::from_json(Some(&json)).unwrap();
+        let map = ev.to_btree_with_all_features();
+
+        assert_eq!(map["id"], "28688495");
+        assert_eq!(map["name"], "gar");
+        assert_eq!(map["owner"], "psyomn");
+        assert_eq!(map["actor"], "jon");
+        assert_eq!(map["org"], "someorg");
+        assert_eq!(map["url"], "https://github.com/psyomn/gar");
+        assert_eq!(map["event_type"], "PushEvent");
+        assert_eq!(map["created_at"], "2015-01-01T15:00:00+00:00");
+    }
+
+    #[test]
+    fn test_to_btree_with_features_of() -> () {
+        let line = r#"{"type":"WatchEvent","actor":"jon",
+                       "repository":{"id":1,"name":"gar","forks":3,"open_issues":5}}"#;
+        let ev = Event::from_line(line).unwrap();
+        let f: Vec<String> = vec!["open_issues".into(), "forks".into(), "created_at".into()];
+        let map = ev.to_btree_with_features_of(&f);
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["forks"], "3");
+        assert_eq!(map["open_issues"], "5");
+        assert_eq!(map["created_at"], "null");
+
+        /* Every feature can be asked for */
+        let all: Vec<String> = FEATURES.iter().map(|f| f.to_string()).collect();
+        assert_eq!(ev.to_btree_with_features_of(&all).len(), FEATURES.len());
+    }
+
+    #[test]
+    fn test_from_json_unknown_schema() -> () {
+        let json = Json::from_str(r#"{"type":"PushEvent"}"#).unwrap();
+        assert!(Event::from_json(Some(&json)).is_none());
+    }
+
+    #[test]
+    fn test_from_line_defers_payload() -> () {
+        let line = r#"{"type":"PushEvent","payload":{"size":1,"shas":[["abc","a@b.c","Fix the {thing}","jon",true]]},
+                       "repository":{"id":1,"name":"gar","owner":"psyomn"}}"#;
+        let mut ev = Event::from_line(line).unwrap();
+        let eager = Event::from_json(Some(&Json::from_str(line).unwrap())).unwrap();
+
+        assert!(ev.raw_payload.is_some());
+        assert_eq!(format!("{:?}", ev.event_type), "Some(Push(None))");
+
+        /* Printing an event shows its payload, decoded, whether it has been decoded yet or not */
+        assert_eq!(format!("{:#?}", ev), format!("{:#?}", eager));
+        assert!(!format!("{:?}", ev).contains("raw_payload"));
+
+        /* Constraints on the payload see it, decoded or not */
+        let cons = vec![Constraint::new("commit_comment", Op::Is, "thing").unwrap()];
+        assert!(ev.satisfies_constraints(&cons));
+        /* ...which is parsed once, for all the constraints that look at it */
+        assert!(ev.decoded.get().is_some());
+
+        ev.decode_payload();
+        assert_eq!(format!("{:?}", ev), format!("{:?}", eager));
+        assert!(ev.satisfies_constraints(&cons));
+
+        /* Types without a payload of interest don't keep it around */
+        let ev = Event::from_line(r#"{"type":"ForkEvent","payload":{},"repo":{"id":1,"name":"psyomn/gar"}}"#).unwrap();
+        assert!(ev.raw_payload.is_none());
+    }
+
+    #[test]
+    fn test_satisfies_payload() -> () {
+        let satisfies = |line: &str, label: &str, op: Op, value: &str| {
+            Event::from_line(line).unwrap().satisfies(&Constraint::new(label, op, value).unwrap())
+        };
+        let delete = r#"{"type":"DeleteEvent","payload":{"ref":"fix-1","ref_type":"branch"},"repo":{"id":1,"name":"psyomn/gar"}}"#;
+        let issue = r#"{"type":"IssuesEvent","payload":{"action":"closed","issue":{"number":7}},"repo":{"id":1,"name":"psyomn/gar"}}"#;
+        let gollum = r#"{"type":"GollumEvent","payload":{"pages":[{"page_name":"Home","action":"edited"},
+                                                                  {"page_name":"FAQ","action":"created"}]},
+                         "repo":{"id":1,"name":"psyomn/gar"}}"#;
+
+        assert!(satisfies(delete, "delete.ref_type", Op::Is, "branch"));
+        assert!(satisfies(delete, "delete.ref", Op::Ne, "main"));
+        assert!(!satisfies(delete, "delete.ref_type", Op::Is, "tag"));
+        assert!(satisfies(issue, "issue.action", Op::Is, "closed"));
+        assert!(satisfies(gollum, "gollum.page", Op::Is, "FAQ"));
+        assert!(satisfies(gollum, "gollum.action", Op::Is, "created"));
+        assert!(!satisfies(gollum, "gollum.page", Op::Is, "Install"));
+
+        /* Events of another type satisfy no constraint on the payload, whatever the operator */
+        assert!(!satisfies(issue, "delete.ref_type", Op::Ne, "branch"));
+        assert!(!satisfies(delete, "push.ref", Op::Is, "refs/heads/main"));
+        assert!(!satisfies(issue, "commit_comment", Op::Is, "fix"));
+        assert!(!satisfies(issue, "commit_comment", Op::Ne, "fix"));
+
+        /* Nor are events of a type gar doesn't know of any type it does */
+        let review = r#"{"type":"PullRequestReviewEvent","payload":{},"repo":{"id":1,"name":"psyomn/gar"}}"#;
+        assert!(!satisfies(review, "type", Op::Is, "push"));
+        assert!(!satisfies(review, "type", Op::Eq, "push"));
+        assert!(satisfies(review, "type", Op::Ne, "push"));
+    }
+
+    #[test]
+    fn test_iter_bytes() -> () {
+        let line = r#"{"type":"WatchEvent","created_at":"2015-01-01T15:00:00Z","actor":{"login":"jon"},"repo":{"id":1,"name":"psyomn/gar"}}"#;
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        write!(enc, "{}\nnot json\n\n{}\n", line, line).unwrap();
+        let gz: Vec<u8> = enc.finish().unwrap();
+
+        let events: Vec<_> = Event::iter_bytes(&gz, "test").collect();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e.is_ok()));
+
+        /* A truncated archive ends with an error */
+        let events: Vec<_> = Event::iter_bytes(&gz[..gz.len() - 4], "test").collect();
+        assert!(events.last().unwrap().is_err());
+    }
+}
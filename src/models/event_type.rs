@@ -1,5 +1,41 @@
+use std::io;
+use std::io::{Read, Write};
+
+use rustc_serialize::json::Json;
+
+use error::Result;
+use models::compact::{Compact, Decoder, Encoder};
 use models::payloads::*;
 
+/// The labels of event types (as in `type:push`), with the names of the types they stand for
+const LABELS: &[(&str, &str)] = &[
+    ("create",                      "CreateEvent"),
+    ("commit_comment",              "CommitCommentEvent"),
+    ("delete",                      "DeleteEvent"),
+    ("deployment",                  "DeploymentEvent"),
+    ("deployment_status",           "DeploymentStatusEvent"),
+    ("download",                    "DownloadEvent"),
+    ("follow",                      "FollowEvent"),
+    ("fork",                        "ForkEvent"),
+    ("fork_apply",                  "ForkApplyEvent"),
+    ("gist",                        "GistEvent"),
+    ("gollum",                      "GollumEvent"),
+    ("issue_comment",               "IssueCommentEvent"),
+    ("issues",                      "IssueEvent"),
+    ("member",                      "MemberEvent"),
+    ("membership",                  "MembershipEvent"),
+    ("page_build",                  "PageBuildEvent"),
+    ("public",                      "PublicEvent"),
+    ("pull_request",                "PullRequestEvent"),
+    ("pull_request_review_comment", "PullRequestReviewCommentEvent"),
+    ("push",                        "PushEvent"),
+    ("release",                     "ReleaseEvent"),
+    ("repository",                  "RepositoryEvent"),
+    ("status",                      "StatusEvent"),
+    ("team_add",                    "TeamAddEvent"),
+    ("watch",                       "WatchEvent"),
+];
+
 #[derive(Debug)]
 pub enum EventType {
     Create,
@@ -30,6 +66,39 @@ pub enum EventType {
     Watch(Option<WatchPayload>),
 }
 
+impl EventType {
+    /// The name (as given by `to_string`) of the event type that a `type:` constraint stands for,
+    /// such as `PushEvent` for `type:push`. `None` for values that aren't an event type.
+    pub fn name_of_label(label: &str) -> Option<&'static str> {
+        LABELS.iter().find(|&&(l, _)| l == label).map(|&(_, name)| name)
+    }
+
+    /// Every value that a `type:` constraint takes
+    pub fn labels() -> Vec<&'static str> {
+        LABELS.iter().map(|&(l, _)| l).collect()
+    }
+
+    /// The same type of event, with its payload parsed out of `payload`. `None` for the types
+    /// whose payload gar doesn't parse.
+    pub fn with_payload(&self, payload: Option<&Json>) -> Option<EventType> {
+        match *self {
+            EventType::Delete(..)       => Some(EventType::Delete(DeletePayload::from_json(payload))),
+            EventType::Gollum(..)       => Some(EventType::Gollum(GollumPayload::from_json(payload))),
+            EventType::IssueComment(..) => Some(EventType::IssueComment(IssueCommentPayload::from_json(payload))),
+            EventType::Issues(..)       => Some(EventType::Issues(IssuePayload::from_json(payload))),
+            EventType::Push(..)         => Some(EventType::Push(PushPayload::from_json(payload))),
+            EventType::Watch(..)        => Some(EventType::Watch(WatchPayload::from_json(payload))),
+            _                           => None,
+        }
+    }
+
+    /// True for the types whose payload gar parses
+    pub fn has_payload(&self) -> bool {
+        matches!(*self, EventType::Delete(..) | EventType::Gollum(..) | EventType::IssueComment(..) |
+                        EventType::Issues(..) | EventType::Push(..) | EventType::Watch(..))
+    }
+}
+
 impl ToString for EventType {
     fn to_string(&self) -> String {
         match *self {
@@ -62,3 +131,70 @@ impl ToString for EventType {
         }
     }
 }
+
+impl Compact for EventType {
+    /// A tag for the variant, followed by its payload where it has one. Tags must never be
+    /// reused: add new variants at the end.
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        match *self {
+            EventType::Create                   => e.u8(0),
+            EventType::Fork                     => e.u8(1),
+            EventType::Other                    => e.u8(2),
+            EventType::CommitComment            => e.u8(3),
+            EventType::Delete(ref p)            => { e.u8(4)?; e.option(p) },
+            EventType::Deployment               => e.u8(5),
+            EventType::DeploymentStatus         => e.u8(6),
+            EventType::Download                 => e.u8(7),
+            EventType::Follow                   => e.u8(8),
+            EventType::ForkApply                => e.u8(9),
+            EventType::Gist                     => e.u8(10),
+            EventType::Gollum(ref p)            => { e.u8(11)?; e.option(p) },
+            EventType::IssueComment(ref p)      => { e.u8(12)?; e.option(p) },
+            EventType::Issues(ref p)            => { e.u8(13)?; e.option(p) },
+            EventType::Member                   => e.u8(14),
+            EventType::Membership               => e.u8(15),
+            EventType::PageBuild                => e.u8(16),
+            EventType::Public                   => e.u8(17),
+            EventType::PullRequest              => e.u8(18),
+            EventType::PullRequestReviewComment => e.u8(19),
+            EventType::Push(ref p)              => { e.u8(20)?; e.option(p) },
+            EventType::Release                  => e.u8(21),
+            EventType::Repository               => e.u8(22),
+            EventType::Status                   => e.u8(23),
+            EventType::TeamAdd                  => e.u8(24),
+            EventType::Watch(ref p)             => { e.u8(25)?; e.option(p) },
+        }
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<EventType> {
+        Ok(match d.u8()? {
+            0  => EventType::Create,
+            1  => EventType::Fork,
+            2  => EventType::Other,
+            3  => EventType::CommitComment,
+            4  => EventType::Delete(d.option()?),
+            5  => EventType::Deployment,
+            6  => EventType::DeploymentStatus,
+            7  => EventType::Download,
+            8  => EventType::Follow,
+            9  => EventType::ForkApply,
+            10 => EventType::Gist,
+            11 => EventType::Gollum(d.option()?),
+            12 => EventType::IssueComment(d.option()?),
+            13 => EventType::Issues(d.option()?),
+            14 => EventType::Member,
+            15 => EventType::Membership,
+            16 => EventType::PageBuild,
+            17 => EventType::Public,
+            18 => EventType::PullRequest,
+            19 => EventType::PullRequestReviewComment,
+            20 => EventType::Push(d.option()?),
+            21 => EventType::Release,
+            22 => EventType::Repository,
+            23 => EventType::Status,
+            24 => EventType::TeamAdd,
+            25 => EventType::Watch(d.option()?),
+            _  => return Err(d.corrupt("unknown event type")),
+        })
+    }
+}
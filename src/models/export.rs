@@ -0,0 +1,315 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use chrono::*;
+
+use error::{GarError, Result};
+use models::event::Event;
+use models::event_type::EventType;
+
+/// Bumped whenever the tables below change in a way that older databases can't be appended to
+const SCHEMA_VERSION: i64 = 1;
+
+/// Repositories are keyed by their github id. Their description and language only come with
+/// legacy events, so they keep the last ones seen; the counters that change over time (watchers,
+/// forks, ...) are kept with each event instead. Users are the owners of repositories, and the
+/// actors and organizations of events, by login.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS archives (
+        name             TEXT PRIMARY KEY,
+        archive_size     INTEGER NOT NULL,
+        archive_modified INTEGER NOT NULL,
+        filter           TEXT NOT NULL,
+        events           INTEGER NOT NULL,
+        exported_at      TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS users (
+        id    INTEGER PRIMARY KEY,
+        login TEXT NOT NULL UNIQUE
+    );
+
+    CREATE TABLE IF NOT EXISTS repos (
+        id          INTEGER PRIMARY KEY,
+        owner_id    INTEGER REFERENCES users (id),
+        name        TEXT NOT NULL,
+        url         TEXT NOT NULL,
+        description TEXT NOT NULL,
+        language    TEXT NOT NULL,
+        has_issues  INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS events (
+        id          INTEGER PRIMARY KEY,
+        archive     TEXT NOT NULL REFERENCES archives (name),
+        type        TEXT,
+        created_at  TEXT,
+        public      INTEGER NOT NULL,
+        repo_id     INTEGER NOT NULL REFERENCES repos (id),
+        actor_id    INTEGER REFERENCES users (id),
+        org_id      INTEGER REFERENCES users (id),
+        watchers    INTEGER NOT NULL,
+        stargazers  INTEGER NOT NULL,
+        forks       INTEGER NOT NULL,
+        open_issues INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS pushes (
+        event_id INTEGER PRIMARY KEY REFERENCES events (id) ON DELETE CASCADE,
+        head     TEXT NOT NULL,
+        ref      TEXT NOT NULL,
+        size     INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS push_commits (
+        event_id    INTEGER NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        position    INTEGER NOT NULL,
+        sha         TEXT NOT NULL,
+        email       TEXT NOT NULL,
+        message     TEXT NOT NULL,
+        author      TEXT NOT NULL,
+        is_distinct INTEGER NOT NULL,
+        PRIMARY KEY (event_id, position)
+    );
+
+    CREATE TABLE IF NOT EXISTS issues (
+        event_id INTEGER PRIMARY KEY REFERENCES events (id) ON DELETE CASCADE,
+        action   TEXT NOT NULL,
+        issue_id INTEGER NOT NULL,
+        number   INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS issue_comments (
+        event_id   INTEGER PRIMARY KEY REFERENCES events (id) ON DELETE CASCADE,
+        comment_id INTEGER NOT NULL,
+        issue_id   INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS gollum_pages (
+        event_id  INTEGER NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+        position  INTEGER NOT NULL,
+        page_name TEXT NOT NULL,
+        action    TEXT NOT NULL,
+        sha       TEXT NOT NULL,
+        summary   TEXT,
+        html_url  TEXT NOT NULL,
+        PRIMARY KEY (event_id, position)
+    );
+
+    CREATE INDEX IF NOT EXISTS events_archive ON events (archive);
+    CREATE INDEX IF NOT EXISTS events_created_at ON events (created_at);
+    CREATE INDEX IF NOT EXISTS events_type ON events (type);
+    CREATE INDEX IF NOT EXISTS events_repo ON events (repo_id);
+    CREATE INDEX IF NOT EXISTS events_actor ON events (actor_id);
+    CREATE INDEX IF NOT EXISTS repos_owner ON repos (owner_id);
+    CREATE INDEX IF NOT EXISTS repos_name ON repos (name);
+    CREATE INDEX IF NOT EXISTS repos_language ON repos (language);
+    CREATE INDEX IF NOT EXISTS issues_issue ON issues (issue_id);
+    CREATE INDEX IF NOT EXISTS issue_comments_issue ON issue_comments (issue_id);
+";
+
+/// A SQLite database that events get exported to, for `gar export sqlite`. Each archive is
+/// exported as a whole, in one transaction, and remembered in the `archives` table along with
+/// the size and modification time it had (see `index::stamp_of`) and the `--where` filter used;
+/// exporting it again replaces its events. That way, running the same export after fetching more
+/// hours only adds the new ones.
+pub struct SqliteExport {
+    conn: Connection,
+}
+
+impl SqliteExport {
+    /// Open the database at `path`, creating it and its tables if needed
+    pub fn open(path: &Path) -> Result<SqliteExport> {
+        let conn: Connection = Connection::open(path)?;
+
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(GarError::Export(format!("{} was made by a newer version of gar", path.display())));
+        }
+
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch(&format!("PRAGMA user_version = {};", SCHEMA_VERSION))?;
+
+        Ok(SqliteExport { conn })
+    }
+
+    /// True if the archive called `name` was already exported with the given `stamp` and `filter`
+    pub fn is_up_to_date(&self, name: &str, stamp: (u64, u64), filter: &str) -> Result<bool> {
+        let exported: Option<(i64, i64, String)> = self.conn
+            .query_row("SELECT archive_size, archive_modified, filter FROM archives WHERE name = ?1",
+                       params![name],
+                       |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .optional()?;
+
+        Ok(exported == Some((stamp.0 as i64, stamp.1 as i64, filter.into())))
+    }
+
+    /// Replace whatever was exported of the archive called `name` with `events`, and return how
+    /// many there were. On error, the database is left as it was.
+    pub fn export<I>(&mut self, name: &str, stamp: (u64, u64), filter: &str, events: I) -> Result<u64>
+        where I: Iterator<Item = Result<Event>>
+    {
+        let tx: Transaction = self.conn.transaction()?;
+
+        tx.execute("DELETE FROM events WHERE archive = ?1", params![name])?;
+        tx.execute("INSERT INTO archives (name, archive_size, archive_modified, filter, events, exported_at)
+                    VALUES (?1, ?2, ?3, ?4, 0, ?5)
+                    ON CONFLICT (name) DO UPDATE SET
+                        archive_size = excluded.archive_size,
+                        archive_modified = excluded.archive_modified,
+                        filter = excluded.filter,
+                        exported_at = excluded.exported_at",
+                   params![name, stamp.0 as i64, stamp.1 as i64, filter, Utc::now().to_rfc3339()])?;
+
+        let mut count: u64 = 0;
+        for e in events {
+            let mut e: Event = e?;
+            e.decode_payload();
+            SqliteExport::insert_event(&tx, name, &e)?;
+            count += 1;
+        }
+
+        tx.execute("UPDATE archives SET events = ?2 WHERE name = ?1", params![name, count as i64])?;
+        tx.commit()?;
+
+        Ok(count)
+    }
+
+    fn insert_event(tx: &Transaction, archive: &str, e: &Event) -> Result<()> {
+        let owner_id: Option<i64> = SqliteExport::user_id(tx, e.owner_nick())?;
+        let actor_id: Option<i64> = SqliteExport::user_id(tx, e.actor())?;
+        let org_id: Option<i64> = SqliteExport::user_id(tx, e.org())?;
+
+        tx.prepare_cached("INSERT INTO repos (id, owner_id, name, url, description, language, has_issues)
+                           VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                           ON CONFLICT (id) DO UPDATE SET
+                               owner_id = excluded.owner_id,
+                               name = excluded.name,
+                               url = excluded.url,
+                               description = COALESCE(NULLIF(excluded.description, ''), description),
+                               language = COALESCE(NULLIF(excluded.language, ''), language),
+                               has_issues = excluded.has_issues OR has_issues")?
+            .execute(params![e.gh_id() as i64, owner_id, e.name(), e.url(), e.description(),
+                             e.language(), e.has_issues()])?;
+
+        /* In UTC, in the format that the date functions of SQLite understand */
+        let created_at: Option<String> = e.created_at().map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string());
+
+        tx.prepare_cached("INSERT INTO events (archive, type, created_at, public, repo_id, actor_id, org_id,
+                                               watchers, stargazers, forks, open_issues)
+                           VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)")?
+            .execute(params![archive, e.event_type().map(|t| t.to_string()), created_at, e.public(),
+                             e.gh_id() as i64, actor_id, org_id, e.watchers() as i64,
+                             e.stargazers() as i64, e.forks() as i64, e.open_issues() as i64])?;
+
+        let event_id: i64 = tx.last_insert_rowid();
+
+        match e.event_type() {
+            Some(&EventType::Push(Some(ref p))) => {
+                tx.prepare_cached("INSERT INTO pushes (event_id, head, ref, size) VALUES (?1, ?2, ?3, ?4)")?
+                    .execute(params![event_id, p.get_head(), p.get_ref(), p.get_size() as i64])?;
+
+                let mut commit = tx.prepare_cached(
+                    "INSERT INTO push_commits (event_id, position, sha, email, message, author, is_distinct)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+                for (ix, sha) in p.get_shas().iter().enumerate() {
+                    commit.execute(params![event_id, ix as i64, sha.get_sha(), sha.get_email(),
+                                           sha.get_comment(), sha.get_author(), sha.is_distinct()])?;
+                }
+            },
+            Some(&EventType::Issues(Some(ref p))) => {
+                tx.prepare_cached("INSERT INTO issues (event_id, action, issue_id, number) VALUES (?1, ?2, ?3, ?4)")?
+                    .execute(params![event_id, p.get_action(), p.get_issue() as i64, p.get_number() as i64])?;
+            },
+            Some(&EventType::IssueComment(Some(ref p))) => {
+                tx.prepare_cached("INSERT INTO issue_comments (event_id, comment_id, issue_id) VALUES (?1, ?2, ?3)")?
+                    .execute(params![event_id, p.get_comment_id() as i64, p.get_issue_id() as i64])?;
+            },
+            Some(&EventType::Gollum(Some(ref p))) => {
+                let mut page = tx.prepare_cached(
+                    "INSERT INTO gollum_pages (event_id, position, page_name, action, sha, summary, html_url)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+                for (ix, pg) in p.get_pages().iter().enumerate() {
+                    page.execute(params![event_id, ix as i64, pg.get_page_name(), pg.get_action(),
+                                         pg.get_sha(), pg.get_summary(), pg.get_html_url()])?;
+                }
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    /// Id of the user with `login`, added if it isn't there yet. `None` for an empty login.
+    fn user_id(tx: &Transaction, login: &str) -> Result<Option<i64>> {
+        if login.is_empty() { return Ok(None) }
+
+        let id: i64 = tx
+            .prepare_cached("INSERT INTO users (login) VALUES (?1)
+                             ON CONFLICT (login) DO UPDATE SET login = excluded.login
+                             RETURNING id")?
+            .query_row(params![login], |r| r.get(0))?;
+
+        Ok(Some(id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use models::event::Event;
+    use models::export::SqliteExport;
+
+    fn count(db: &SqliteExport, sql: &str) -> i64 {
+        db.conn.query_row(sql, [], |r| r.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_export() -> () {
+        let lines = [
+            r#"{"created_at":"2013-01-01T01:00:00-08:00","public":true,"type":"PushEvent","actor":"jon",
+                "payload":{"head":"abc","ref":"refs/heads/master","size":2,
+                           "shas":[["abc","a@b.c","Fix the thing","jon",true],["def","a@b.c","And this","jon",false]]},
+                "repository":{"id":724712,"name":"rust","owner":"mozilla","language":"Rust","watchers":10}}"#,
+            r#"{"type":"IssuesEvent","created_at":"2015-01-01T15:00:00Z","actor":{"login":"jon"},
+                "repo":{"id":724712,"name":"mozilla/rust"},
+                "payload":{"action":"closed","issue":{"id":7,"number":3}}}"#,
+            r#"{"type":"GollumEvent","actor":{"login":"ann"},"org":{"login":"mozilla"},
+                "repo":{"id":1,"name":"psyomn/gar"},
+                "payload":{"pages":[{"page_name":"Home","action":"edited","sha":"abc","summary":null}]}}"#,
+        ];
+        let events = || lines.iter().map(|l| Ok(Event::from_line(l).unwrap()));
+
+        let mut db = SqliteExport::open(Path::new(":memory:")).unwrap();
+        assert!(!db.is_up_to_date("a.json.gz", (1, 2), "").unwrap());
+        assert_eq!(db.export("a.json.gz", (1, 2), "", events()).unwrap(), 3);
+        assert!(db.is_up_to_date("a.json.gz", (1, 2), "").unwrap());
+        assert!(!db.is_up_to_date("a.json.gz", (1, 3), "").unwrap());
+        assert!(!db.is_up_to_date("a.json.gz", (1, 2), "language:Rust").unwrap());
+
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM users"), 4);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM repos"), 2);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM push_commits WHERE message = 'And this'"), 1);
+        assert_eq!(count(&db, "SELECT number FROM issues WHERE action = 'closed'"), 3);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM gollum_pages WHERE summary IS NULL"), 1);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM events WHERE created_at = '2013-01-01 09:00:00'"), 1);
+
+        /* The language of legacy events survives the events that don't carry one */
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM repos WHERE language = 'Rust' AND name = 'rust'"), 1);
+
+        /* Exporting an archive again replaces its events, payloads included */
+        assert_eq!(db.export("a.json.gz", (1, 3), "", events().take(1)).unwrap(), 1);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM events"), 1);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM issues"), 0);
+        assert_eq!(count(&db, "SELECT events FROM archives"), 1);
+
+        /* A failed export leaves the archive as it was */
+        let failing = events().chain(vec![Err(::error::GarError::Parse("bad".into()))]);
+        assert!(db.export("a.json.gz", (1, 4), "", failing).is_err());
+        assert!(db.is_up_to_date("a.json.gz", (1, 3), "").unwrap());
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM events"), 1);
+    }
+}
@@ -0,0 +1,496 @@
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use error::{GarError, Result};
+use models::constraint::{Constraint, Op};
+use models::event::Event;
+
+/// A `--where` clause, parsed once and then checked against each event. Constraints
+/// (`label:value`) are combined with `NOT`, `AND` (or a comma) and `OR`, and grouped with
+/// parentheses. `NOT` binds tightest and `OR` loosest, so that
+/// `language:Rust OR language:Go,NOT type:fork` is `language:Rust OR (language:Go AND NOT type:fork)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Is(Constraint),
+    Not(Box<Expr>),
+    /// True when all of them are; an empty clause is an empty `And`, which anything satisfies
+    And(Vec<Expr>),
+    /// True when any of them is
+    Or(Vec<Expr>),
+}
+
+impl Expr {
+    /// Parse a `--where` clause. Errors say at which column of `text` things went wrong.
+    pub fn parse(text: &str) -> Result<Expr> {
+        let tokens: Vec<(usize, Token)> = tokenize(text)?;
+        if tokens.is_empty() { return Ok(Expr::And(vec![])) }
+
+        let mut parser = Parser { text, tokens: tokens.into_iter().peekable() };
+        let expr: Expr = parser.or()?;
+
+        match parser.tokens.next() {
+            Some((col, t)) => Err(error_at(text, col, &format!("unexpected {}", t.describe()))),
+            None => Ok(expr),
+        }
+    }
+
+    /// True if `e` satisfies the clause. Constraints are checked left to right, and only as far
+    /// as needed to tell.
+    pub fn matches(&self, e: &Event) -> bool {
+        match *self {
+            Expr::Is(ref cons) => e.satisfies(cons),
+            Expr::Not(ref x) => !x.matches(e),
+            Expr::And(ref xs) => xs.iter().all(|x| x.matches(e)),
+            Expr::Or(ref xs) => xs.iter().any(|x| x.matches(e)),
+        }
+    }
+
+    /// True if some constraint of the clause looks into payloads
+    pub fn needs_payload(&self) -> bool {
+        match *self {
+            Expr::Is(ref cons) => cons.needs_payload(),
+            Expr::Not(ref x) => x.needs_payload(),
+            Expr::And(ref xs) | Expr::Or(ref xs) => xs.iter().any(|x| x.needs_payload()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Comma,
+    Not,
+    And,
+    Or,
+    /// A constraint, with the text it was written as
+    Term(Constraint, String),
+}
+
+impl Token {
+    /// What the token is, for error messages
+    fn describe(&self) -> String {
+        match *self {
+            Token::Open => "'('".into(),
+            Token::Close => "')'".into(),
+            Token::Comma => "','".into(),
+            Token::Not => "NOT".into(),
+            Token::And => "AND".into(),
+            Token::Or => "OR".into(),
+            Token::Term(_, ref text) => text.clone(),
+        }
+    }
+}
+
+/// Split `text` into tokens, each with the column (counting from 1) it starts at.
+///
+/// A constraint is a label, an operator (`:`, `=`, `!=`, `<`, `<=`, `>` or `>=`) and a value
+/// (see `scan_value`).
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>> {
+    let cs: Vec<char> = text.chars().collect();
+    let mut tokens: Vec<(usize, Token)> = vec![];
+    let mut i: usize = 0;
+
+    while i < cs.len() {
+        let col: usize = i + 1;
+
+        match cs[i] {
+            c if c.is_whitespace() => { i += 1; continue },
+            '(' => { tokens.push((col, Token::Open)); i += 1; continue },
+            ')' => { tokens.push((col, Token::Close)); i += 1; continue },
+            ',' => { tokens.push((col, Token::Comma)); i += 1; continue },
+            _ => {},
+        }
+
+        let mut j: usize = i;
+        while j < cs.len() && !is_word_end(cs[j]) { j += 1 }
+        let word: String = cs[i..j].iter().collect();
+
+        let (op, op_len): (Op, usize) = match op_at(&cs[j..]) {
+            Some(op) => op,
+            None => {
+                let token: Token = match word.as_ref() {
+                    "NOT" => Token::Not,
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "" => return Err(error_at(text, col, &format!("unexpected {:?}", cs[i]))),
+                    _ => return Err(error_at(text, col, &format!("expected label:value, not {:?}", word))),
+                };
+                tokens.push((col, token));
+                i = j;
+                continue;
+            },
+        };
+
+        if word.is_empty() {
+            return Err(error_at(text, col, &format!("expected a label before '{}'", op)));
+        }
+
+        let (value, k): (Written, usize) = scan_value(text, &cs, j + op_len)?;
+        let cons: Result<Constraint> = match value {
+            Written::Plain(ref v) => Constraint::new(&word, op, v),
+            Written::Quoted(ref v) => Constraint::new_quoted(&word, op, v),
+            Written::Regex(ref pattern, ignore_case) => Constraint::new_regex(&word, op, pattern, ignore_case),
+        };
+        let cons: Constraint = cons.map_err(|e| match e {
+            GarError::Query(msg) | GarError::Parse(msg) => error_at(text, col, &msg),
+            e => e,
+        })?;
+        let written: String = cs[i..k].iter().collect();
+        tokens.push((col, Token::Term(cons, written.trim_end().into())));
+        i = k;
+    }
+
+    Ok(tokens)
+}
+
+/// A value, as written after the operator of a constraint
+enum Written {
+    /// With its escapes taken out
+    Plain(String),
+    /// `"text"`, with its escapes taken out
+    Quoted(String),
+    /// `/pattern/`, or `/pattern/i` to ignore case
+    Regex(String, bool),
+}
+
+/// The value starting at `cs[start]`, and where it ends. It is one of:
+///
+/// * a quoted string, `"fast, safe"`, in which a backslash makes the next character part of the
+///   string (so `\"` is a quote, and `\\` a backslash). It is taken as is, even by the labels
+///   that otherwise take a regex (see `Constraint::new_quoted`).
+/// * a regex literal, `/^rust-.*$/` or `/^rust-.*$/i` to ignore case, in which `\/` is a slash;
+///   other backslashes are left to the regex
+/// * anything else, running up to the next comma, the `)` closing a group, or a space followed
+///   by `AND` or `OR`. It can hold spaces, and balanced parentheses (as in `name:(gar|rust)`);
+///   spaces at its end are dropped. A space followed by `NOT` or by another constraint is an
+///   error rather than part of the value, since a connective was most likely left out; such a
+///   value has to be quoted, or its spaces escaped. A backslash before a space, a quote, a slash, a comma, a
+///   parenthesis or another backslash makes it part of the value; other backslashes are kept,
+///   so that `name:gar\.rs` still reaches the regex as written.
+fn scan_value(text: &str, cs: &[char], start: usize) -> Result<(Written, usize)> {
+    let escapable = |c: char| c.is_whitespace() || "\"/,()\\".contains(c);
+
+    match cs.get(start) {
+        Some(&'"') => {
+            let mut value: String = String::new();
+            let mut k: usize = start + 1;
+
+            loop {
+                match cs.get(k) {
+                    None => return Err(error_at(text, start + 1, "unclosed quote")),
+                    Some(&'"') => break,
+                    Some(&'\\') if k + 1 < cs.len() => { value.push(cs[k + 1]); k += 2 },
+                    Some(&c) => { value.push(c); k += 1 },
+                }
+            }
+
+            Ok((Written::Quoted(value), value_end(text, cs, k + 1)?))
+        },
+        Some(&'/') => {
+            let mut pattern: String = String::new();
+            let mut k: usize = start + 1;
+
+            loop {
+                match cs.get(k) {
+                    None => return Err(error_at(text, start + 1, "unclosed regex")),
+                    Some(&'/') => break,
+                    Some(&'\\') if cs.get(k + 1) == Some(&'/') => { pattern.push('/'); k += 2 },
+                    Some(&c) => { pattern.push(c); k += 1 },
+                }
+            }
+
+            let mut ignore_case: bool = false;
+            k += 1;
+            while k < cs.len() && cs[k].is_alphanumeric() {
+                match cs[k] {
+                    'i' => ignore_case = true,
+                    c => return Err(error_at(text, k + 1, &format!("unknown regex flag {:?}", c))),
+                }
+                k += 1;
+            }
+
+            Ok((Written::Regex(pattern, ignore_case), value_end(text, cs, k)?))
+        },
+        _ => {
+            let mut value: String = String::new();
+            /* How much of the value to keep, whatever trailing spaces it has */
+            let mut kept: usize = 0;
+            let mut k: usize = start;
+            let mut depth: usize = 0;
+
+            while k < cs.len() {
+                match cs[k] {
+                    '\\' if cs.get(k + 1).is_some_and(|&c| escapable(c)) => {
+                        value.push(cs[k + 1]);
+                        kept = value.len();
+                        k += 2;
+                        continue;
+                    },
+                    ',' if depth == 0 => break,
+                    ')' if depth == 0 => break,
+                    c if c.is_whitespace() && depth == 0 && operator_follows(&cs[k..]) => break,
+                    c if c.is_whitespace() && depth == 0 && term_follows(&cs[k..]) => {
+                        let col: usize = k + cs[k..].iter().take_while(|c| c.is_whitespace()).count() + 1;
+                        return Err(error_at(text, col, "expected AND, OR or ','"));
+                    },
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {},
+                }
+                value.push(cs[k]);
+                k += 1;
+            }
+
+            let trimmed: usize = value.trim_end().len().max(kept);
+            value.truncate(trimmed);
+            Ok((Written::Plain(value), k))
+        },
+    }
+}
+
+/// Where a quoted or regex value that ended right before `cs[k]` is followed by what can come
+/// after a value; which is `k`, unless that is something else
+fn value_end(text: &str, cs: &[char], k: usize) -> Result<usize> {
+    match cs.get(k) {
+        None => Ok(k),
+        Some(&c) if c.is_whitespace() || c == ',' || c == ')' => Ok(k),
+        Some(&c) => Err(error_at(text, k + 1, &format!("unexpected {:?} after the value", c))),
+    }
+}
+
+/// True for the characters that end a label or keyword
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || "(),:<>=!".contains(c)
+}
+
+/// The operator that `cs` starts with, and how many characters it takes
+fn op_at(cs: &[char]) -> Option<(Op, usize)> {
+    match (cs.first(), cs.get(1)) {
+        (Some(':'), _) => Some((Op::Is, 1)),
+        (Some('='), _) => Some((Op::Eq, 1)),
+        (Some('!'), Some('=')) => Some((Op::Ne, 2)),
+        (Some('<'), Some('=')) => Some((Op::Le, 2)),
+        (Some('<'), _) => Some((Op::Lt, 1)),
+        (Some('>'), Some('=')) => Some((Op::Ge, 2)),
+        (Some('>'), _) => Some((Op::Gt, 1)),
+        _ => None,
+    }
+}
+
+/// True if `cs` is some spaces followed by `AND` or `OR`, as a word of its own
+fn operator_follows(cs: &[char]) -> bool {
+    let rest: Vec<char> = cs.iter().cloned().skip_while(|c| c.is_whitespace()).collect();
+
+    ["AND", "OR"].iter().any(|op| {
+        let op: Vec<char> = op.chars().collect();
+        rest.starts_with(&op) && rest.get(op.len()).is_none_or(|c| c.is_whitespace() || *c == '(')
+    })
+}
+
+/// True if `cs` is some spaces followed by `NOT`, or by a label and its operator
+fn term_follows(cs: &[char]) -> bool {
+    let rest: Vec<char> = cs.iter().cloned().skip_while(|c| c.is_whitespace()).collect();
+    let len: usize = rest.iter().take_while(|&&c| !is_word_end(c)).count();
+    let word: String = rest[..len].iter().collect();
+
+    (word == "NOT" && rest.get(len).is_none_or(|c| c.is_whitespace() || *c == '('))
+        || (len > 0 && op_at(&rest[len..]).is_some())
+}
+
+/// An error about `text`, pointing at its column `col`
+fn error_at(text: &str, col: usize, msg: &str) -> GarError {
+    GarError::Query(format!("column {}: {}\n    {}\n    {}^", col, msg, text, " ".repeat(col - 1)))
+}
+
+/// Recursive descent over the tokens of a clause, one function per level of precedence
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Peekable<IntoIter<(usize, Token)>>,
+}
+
+impl<'a> Parser<'a> {
+    /// or := and ("OR" and)*
+    fn or(&mut self) -> Result<Expr> {
+        let mut xs: Vec<Expr> = vec![self.and()?];
+        while self.eat(|t| *t == Token::Or) {
+            xs.push(self.and()?);
+        }
+
+        Ok(if xs.len() == 1 { xs.remove(0) } else { Expr::Or(xs) })
+    }
+
+    /// and := not (("AND" | ",") not)*
+    fn and(&mut self) -> Result<Expr> {
+        let mut xs: Vec<Expr> = vec![self.not()?];
+        while self.eat(|t| *t == Token::And || *t == Token::Comma) {
+            xs.push(self.not()?);
+        }
+
+        Ok(if xs.len() == 1 { xs.remove(0) } else { Expr::And(xs) })
+    }
+
+    /// not := "NOT" not | "(" or ")" | label:value
+    fn not(&mut self) -> Result<Expr> {
+        match self.tokens.next() {
+            Some((_, Token::Not)) => Ok(Expr::Not(Box::new(self.not()?))),
+            Some((open, Token::Open)) => {
+                let x: Expr = self.or()?;
+                match self.tokens.next() {
+                    Some((_, Token::Close)) => Ok(x),
+                    Some((col, t)) =>
+                        Err(error_at(self.text, col, &format!("expected ')', not {}", t.describe()))),
+                    None => Err(error_at(self.text, open, "unclosed '('")),
+                }
+            },
+            Some((_, Token::Term(cons, _))) => Ok(Expr::Is(cons)),
+            Some((col, t)) =>
+                Err(error_at(self.text, col, &format!("expected label:value, NOT or '(', not {}", t.describe()))),
+            None => Err(error_at(self.text, self.end(), "expected label:value, NOT or '('")),
+        }
+    }
+
+    /// Move past the next token if `f` is true for it
+    fn eat<F: Fn(&Token) -> bool>(&mut self, f: F) -> bool {
+        match self.tokens.peek() {
+            Some((_, t)) if f(t) => { self.tokens.next(); true },
+            _ => false,
+        }
+    }
+
+    /// The column right after the end of the clause
+    fn end(&self) -> usize {
+        self.text.chars().count() + 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use models::constraint::{Constraint, Op};
+    use models::event::Event;
+    use models::expr::Expr;
+
+    fn is(label: &str, value: &str) -> Expr {
+        Expr::Is(Constraint::new(label, Op::Is, value).unwrap())
+    }
+
+    #[test]
+    fn test_parse() -> () {
+        assert_eq!(Expr::parse("").unwrap(), Expr::And(vec![]));
+        assert_eq!(Expr::parse("language:Rust").unwrap(), is("language", "Rust"));
+        assert_eq!(Expr::parse("language:Rust,type:create").unwrap(),
+                   Expr::And(vec![is("language", "Rust"), is("type", "create")]));
+        assert_eq!(Expr::parse("language:Rust OR language:Go,NOT type:fork").unwrap(),
+                   Expr::Or(vec![is("language", "Rust"),
+                                 Expr::And(vec![is("language", "Go"),
+                                                Expr::Not(Box::new(is("type", "fork")))])]));
+        assert_eq!(Expr::parse("(language:Rust OR language:Go) AND NOT type:fork").unwrap(),
+                   Expr::And(vec![Expr::Or(vec![is("language", "Rust"), is("language", "Go")]),
+                                  Expr::Not(Box::new(is("type", "fork")))]));
+
+        /* Values keep their spaces, colons and balanced parentheses */
+        assert_eq!(Expr::parse("commit_comment:fix the build ,name:(gar|rust)").unwrap(),
+                   Expr::And(vec![is("commit_comment", "fix the build"), is("name", "(gar|rust)")]));
+        assert_eq!(Expr::parse("(description:a:b)").unwrap(), is("description", "a:b"));
+
+        assert_eq!(Expr::parse("forks>=10 OR created_at<2015-01-02T10:00:00Z").unwrap(),
+                   Expr::Or(vec![Expr::Is(Constraint::new("forks", Op::Ge, "10").unwrap()),
+                                 Expr::Is(Constraint::new("created_at", Op::Lt, "2015-01-02T10:00:00Z").unwrap())]));
+        assert_eq!(Expr::parse("-watchers:5,language!=Go").unwrap(),
+                   Expr::And(vec![Expr::Is(Constraint::new("watchers", Op::Lt, "5").unwrap()),
+                                  Expr::Is(Constraint::new("language", Op::Ne, "Go").unwrap())]));
+        assert_eq!(Expr::parse("description:ORANGE OR").unwrap_err().to_string(),
+                   "query error: column 22: expected label:value, NOT or '('\n    \
+                    description:ORANGE OR\n                         ^");
+    }
+
+    #[test]
+    fn test_parse_values() -> () {
+        let text = |text: &str, value: &str| {
+            let (label, _) = text.split_at(text.find(':').unwrap());
+            assert_eq!(Expr::parse(text).unwrap(), is(label, value), "{}", text);
+        };
+        let quoted = |text: &str, value: &str| {
+            let (label, _) = text.split_at(text.find(':').unwrap());
+            assert_eq!(Expr::parse(text).unwrap(),
+                       Expr::Is(Constraint::new_quoted(label, Op::Is, value).unwrap()), "{}", text);
+        };
+
+        quoted(r#"description:"fast, safe""#, "fast, safe");
+        quoted(r#"actor:"say \"hi\" \\ bye""#, r#"say "hi" \ bye"#);
+        quoted(r#"language:"" "#, "");
+        quoted(r#"description:"OR AND (""#, "OR AND (");
+        text(r#"description:fast\, safe"#, "fast, safe");
+        text(r#"description:trailing\ "#, "trailing ");
+        text(r#"owner:\(not a group"#, "(not a group");
+        text(r#"name:gar\.rs"#, r#"gar\.rs"#);
+        text(r#"description:fix the build"#, "fix the build");
+        text(r#"description:fast\ NOT\ type:fork"#, "fast NOT type:fork");
+        quoted(r#"description:"fast NOT type:fork""#, "fast NOT type:fork");
+
+        let regex = |text: &str| Expr::parse(text).unwrap();
+        assert_eq!(regex(r#"name:/^rust-.*$/i"#),
+                   Expr::Is(Constraint::new_regex("name", Op::Is, "^rust-.*$", true).unwrap()));
+        assert_eq!(regex(r#"(owner:/a\/b\d/,name:x)"#),
+                   Expr::And(vec![Expr::Is(Constraint::new_regex("owner", Op::Is, r#"a/b\d"#, false).unwrap()),
+                                  is("name", "x")]));
+        assert_eq!(regex(r#"language!=/^c/ OR name:a"#),
+                   Expr::Or(vec![Expr::Is(Constraint::new_regex("language", Op::Ne, "^c", false).unwrap()),
+                                 is("name", "a")]));
+    }
+
+    #[test]
+    fn test_parse_errors() -> () {
+        fn column_of(text: &str) -> usize {
+            let msg: String = Expr::parse(text).unwrap_err().to_string();
+            let col: &str = msg.trim_start_matches("query error: column ").split(':').next().unwrap();
+            col.parse().unwrap()
+        }
+
+        assert_eq!(column_of("language"), 1);
+        assert_eq!(column_of(":Rust"), 1);
+        assert_eq!(column_of("language:Rust OR"), 17);
+        assert_eq!(column_of("(language:Rust OR language:Go"), 1);
+        assert_eq!(column_of("language:Go OR ((language:Rust)"), 16);
+        assert_eq!(column_of("language:Rust)"), 14);
+        assert_eq!(column_of("language:Rust,,type:fork"), 15);
+        assert_eq!(column_of("NOT AND language:Rust"), 5);
+        assert_eq!(column_of("forks>1,language>Go"), 9);
+        assert_eq!(column_of("forks!10"), 1);
+        assert_eq!(column_of(">=10"), 1);
+        assert_eq!(column_of("language:Rust OR lanuage:Go"), 18);
+        assert_eq!(column_of("(type:pussh)"), 2);
+        assert_eq!(column_of("name:rust OR name:("), 14);
+        assert_eq!(column_of("created_at:2015-13"), 1);
+        assert_eq!(column_of(r#"language:Rust,description:"fast"#), 27);
+        assert_eq!(column_of(r#"description:"fast"safe"#), 19);
+        assert_eq!(column_of(r#"name:/^rust"#), 6);
+        assert_eq!(column_of(r#"name:/^rust/x"#), 13);
+        assert_eq!(column_of(r#"forks:/1/"#), 1);
+        assert_eq!(column_of(r#"description:"\"#), 13);
+        assert_eq!(column_of(r#"language:"\\" AND"#), 18);
+        assert_eq!(column_of("language:Rust NOT type:fork"), 15);
+        assert_eq!(column_of("language:Rust type:fork"), 15);
+        assert_eq!(column_of("commit_comment:fix the build forks>=10"), 30);
+        assert_eq!(column_of("language:Rust  NOT"), 16);
+    }
+
+    #[test]
+    fn test_matches() -> () {
+        let line = r#"{"type":"ForkEvent","repository":{"id":1,"name":"gar","language":"Rust"}}"#;
+        let e = Event::from_line(line).unwrap();
+        let matches = |text: &str| Expr::parse(text).unwrap().matches(&e);
+
+        assert!(matches(""));
+        assert!(matches("language:Rust OR language:Go"));
+        assert!(!matches("(language:Rust OR language:Go),NOT type:fork"));
+        assert!(matches("NOT (language:Go OR type:push)"));
+        assert!(matches("NOT NOT name:^gar$"));
+        assert!(!matches("language:Go,name:gar OR type:push"));
+        assert!(matches("language!=Go,id:1,forks<1"));
+
+        /* Events with no date satisfy no date constraint */
+        assert!(!matches("created_at!=2015"));
+        assert!(!matches("language!=Rust OR id:2..3"));
+    }
+}
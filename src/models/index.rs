@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use rustc_serialize::json::{Json, Object};
+
+use config;
+use error::{GarError, Result};
+use models::constraint::{Constraint, Field, Op};
+use models::event::Event;
+use models::expr::Expr;
+use models::json_helpers::JsonHelper;
+
+/// Bumped whenever the layout of an index changes, so that older ones get rebuilt
+const INDEX_VERSION: u64 = 1;
+
+/// A summary of what an archive contains: enough to tell that a query can't match anything in it,
+/// without reading it. Each archive gets its own index file in the index folder (see
+/// `config::index_file_path`), which is only trusted while the archive keeps the size and
+/// modification time it had when it was indexed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArchiveIndex {
+    /// Size of the archive when it was indexed
+    pub archive_size: u64,
+    /// Modification time of the archive when it was indexed, in nanoseconds since the epoch
+    pub archive_modified: u64,
+    /// How many events the archive holds
+    pub events: u64,
+    /// How many events of each type (as named by `EventType::to_string`)
+    pub types: BTreeMap<String, u64>,
+    /// How many events are of a type that gar doesn't know
+    pub untyped: u64,
+    pub languages: BTreeSet<String>,
+    pub owners: BTreeSet<String>,
+    /// Repository names
+    pub names: BTreeSet<String>,
+}
+
+impl ArchiveIndex {
+    /// An index of nothing yet, for the archive at `p` as it is now. Fill it with `add`.
+    pub fn for_archive(p: &Path) -> Result<ArchiveIndex> {
+        let (archive_size, archive_modified) = stamp_of(p)?;
+
+        Ok(ArchiveIndex { archive_size, archive_modified, ..ArchiveIndex::default() })
+    }
+
+    /// Read the whole archive at `p`, and index it
+    pub fn build(p: &Path) -> Result<ArchiveIndex> {
+        let mut idx = ArchiveIndex::for_archive(p)?;
+
+        for e in Event::iter_path(p.to_path_buf()) {
+            idx.add(&e?);
+        }
+
+        Ok(idx)
+    }
+
+    /// Count one more event of the archive
+    pub fn add(&mut self, e: &Event) {
+        self.events += 1;
+
+        match e.event_type() {
+            Some(t) => *self.types.entry(t.to_string()).or_insert(0) += 1,
+            None => self.untyped += 1,
+        }
+
+        if !self.languages.contains(e.language()) {
+            self.languages.insert(e.language().into());
+        }
+        if !self.owners.contains(e.owner_nick()) {
+            self.owners.insert(e.owner_nick().into());
+        }
+        if !self.names.contains(e.name()) {
+            self.names.insert(e.name().into());
+        }
+    }
+
+    /// False when no event of the archive can satisfy `expr` (see `Expr::matches`). Only
+    /// `language`, `owner`, `name` and `type` constraints are looked at; anything else could
+    /// match, as far as the index knows.
+    pub fn may_match(&self, expr: &Expr) -> bool {
+        match *expr {
+            Expr::Is(ref cons) => self.may_satisfy(cons),
+            /* That no event satisfies a constraint says nothing about its negation */
+            Expr::Not(..) => true,
+            Expr::And(ref xs) => xs.iter().all(|x| self.may_match(x)),
+            Expr::Or(ref xs) => xs.iter().any(|x| self.may_match(x)),
+        }
+    }
+
+    /// False when no event of the archive can satisfy `cons`. These are checked the same way as
+    /// `Event::satisfies` does, against every value found in the archive.
+    fn may_satisfy(&self, cons: &Constraint) -> bool {
+        match cons.field {
+            Field::Language => self.languages.iter().any(|l| cons.holds_for_text(l)),
+            Field::Owner => self.owners.iter().any(|o| cons.holds_for_text(o)),
+            Field::Name => self.names.iter().any(|n| cons.holds_for_text(n)),
+            /* Events of an unknown type satisfy only `!=` type constraints */
+            Field::Type =>
+                (self.untyped > 0 && cons.op == Op::Ne) || self.types.keys().any(|t| cons.holds_for_text(t)),
+            _ => true,
+        }
+    }
+
+    /// True if the archive at `p` is still the one that was indexed
+    pub fn is_fresh_for(&self, p: &Path) -> bool {
+        match stamp_of(p) {
+            Ok(stamp) => stamp == (self.archive_size, self.archive_modified),
+            Err(..) => false,
+        }
+    }
+
+    /// The index of the archive at `p`, if there is one and the archive hasn't changed since
+    pub fn load_fresh(p: &Path) -> Option<ArchiveIndex> {
+        ArchiveIndex::load(p).filter(|idx| idx.is_fresh_for(p))
+    }
+
+    /// The index of the archive at `p`, whether up to date or not. Missing, unreadable and
+    /// outdated index files are all as good as no index.
+    pub fn load(p: &Path) -> Option<ArchiveIndex> {
+        let path: PathBuf = config::index_file_path(p).ok()?;
+        let contents: String = fs::read_to_string(path).ok()?;
+
+        ArchiveIndex::from_json(&Json::from_str(&contents).ok()?)
+    }
+
+    /// Write the index of the archive at `p`. Like `Archive::store`, it goes through a temporary
+    /// file, so readers never see half an index. No lock is needed: two processes indexing the
+    /// same archive write the same thing.
+    pub fn store(&self, p: &Path) -> Result<()> {
+        let dest: PathBuf = config::index_file_path(p)?;
+        fs::create_dir_all(config::index_path()?)?;
+
+        let mut tmp: PathBuf = dest.clone();
+        tmp.set_extension(format!("{}.tmp", std::process::id()));
+
+        let written = File::create(&tmp)
+            .and_then(|mut f| f.write_all(self.to_json().to_string().as_bytes()))
+            .and_then(|_| fs::rename(&tmp, &dest));
+
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+
+        Ok(written?)
+    }
+
+    /// Remove the index of the archive at `p`, if there is one
+    pub fn remove(p: &Path) {
+        if let Ok(path) = config::index_file_path(p) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        fn strings(s: &BTreeSet<String>) -> Json {
+            Json::Array(s.iter().map(|e| Json::String(e.clone())).collect())
+        }
+
+        let mut obj: Object = Object::new();
+        let types: Object = self.types.iter().map(|(k, v)| (k.clone(), Json::U64(*v))).collect();
+
+        obj.insert("version".into(), Json::U64(INDEX_VERSION));
+        obj.insert("archive_size".into(), Json::U64(self.archive_size));
+        obj.insert("archive_modified".into(), Json::U64(self.archive_modified));
+        obj.insert("events".into(), Json::U64(self.events));
+        obj.insert("types".into(), Json::Object(types));
+        obj.insert("untyped".into(), Json::U64(self.untyped));
+        obj.insert("languages".into(), strings(&self.languages));
+        obj.insert("owners".into(), strings(&self.owners));
+        obj.insert("names".into(), strings(&self.names));
+
+        Json::Object(obj)
+    }
+
+    fn from_json(json: &Json) -> Option<ArchiveIndex> {
+        fn strings(j: Option<&Json>) -> Option<BTreeSet<String>> {
+            j?.as_array()?.iter().map(|e| e.as_string().map(|s| s.into())).collect()
+        }
+
+        let obj = json.as_object()?;
+
+        if JsonHelper::number_or_zero(obj.get("version")) != INDEX_VERSION { return None }
+
+        let types: BTreeMap<String, u64> = obj.get("types")?
+            .as_object()?
+            .iter()
+            .map(|(k, v)| v.as_u64().map(|n| (k.clone(), n)))
+            .collect::<Option<BTreeMap<String, u64>>>()?;
+
+        Some(ArchiveIndex {
+            archive_size: obj.get("archive_size")?.as_u64()?,
+            archive_modified: obj.get("archive_modified")?.as_u64()?,
+            events: obj.get("events")?.as_u64()?,
+            types,
+            untyped: obj.get("untyped")?.as_u64()?,
+            languages: strings(obj.get("languages"))?,
+            owners: strings(obj.get("owners"))?,
+            names: strings(obj.get("names"))?,
+        })
+    }
+}
+
+/// Size and modification time (in nanoseconds) of the file at `p`. Things made out of an archive
+/// (indexes, compacted archives) keep the stamp of the archive they were made from, so that they
+/// can tell when it has changed.
+pub fn stamp_of(p: &Path) -> Result<(u64, u64)> {
+    let meta = fs::metadata(p)?;
+    let modified = meta.modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| GarError::Parse(format!("bad modification time: {}", e)))?;
+
+    Ok((meta.len(), modified.as_nanos() as u64))
+}
+
+#[cfg(test)]
+mod test {
+    use models::event::Event;
+    use models::expr::Expr;
+    use models::index::ArchiveIndex;
+
+    fn cons(label: &str, value: &str) -> Expr {
+        Expr::parse(&format!("{}:{}", label, value)).unwrap()
+    }
+
+    #[test]
+    fn test_may_match() -> () {
+        let line = r#"{"type":"WatchEvent","actor":"jon",
+                       "repository":{"id":1,"name":"gar","owner":"psyomn","language":"Rust"}}"#;
+        let mut idx = ArchiveIndex::default();
+        idx.add(&Event::from_line(line).unwrap());
+
+        assert_eq!(idx.events, 1);
+        assert_eq!(idx.types["WatchEvent"], 1);
+
+        assert!(idx.may_match(&cons("language", "Rust")));
+        assert!(!idx.may_match(&cons("language", "Haskell")));
+        assert!(idx.may_match(&cons("owner", "psyomn")));
+        assert!(!idx.may_match(&cons("owner", "mozilla")));
+        assert!(idx.may_match(&cons("name", "^GA")));
+        assert!(!idx.may_match(&cons("name", "rust")));
+        assert!(idx.may_match(&cons("type", "watch")));
+        assert!(!idx.may_match(&cons("type", "push")));
+        assert!(idx.may_match(&cons("actor", "nobody")));
+
+        let clause = |text: &str| idx.may_match(&Expr::parse(text).unwrap());
+        assert!(clause("language:Haskell OR owner:psyomn"));
+        assert!(!clause("language:Haskell OR owner:mozilla"));
+        assert!(!clause("language:Rust,type:push"));
+        assert!(clause("NOT language:Rust"));
+        assert!(!clause("language=Go"));
+
+        /* Every event is in Rust, and is a watch */
+        assert!(!clause("language!=Rust"));
+        assert!(clause("language!=Go"));
+        assert!(!clause("type!=watch"));
+
+        /* An event of an unknown type satisfies only `!=` type constraints */
+        idx.add(&Event::from_line(r#"{"type":"NewEvent","repository":{"id":2}}"#).unwrap());
+        assert!(!idx.may_match(&cons("type", "push")));
+        assert!(!idx.may_match(&Expr::parse("type=push").unwrap()));
+        assert!(idx.may_match(&Expr::parse("type!=watch").unwrap()));
+    }
+
+    #[test]
+    fn test_json_round_trip() -> () {
+        let line = r#"{"type":"PushEvent","repo":{"id":1,"name":"psyomn/gar"}}"#;
+        let mut idx = ArchiveIndex { archive_size: 10, archive_modified: 20, ..ArchiveIndex::default() };
+        idx.add(&Event::from_line(line).unwrap());
+
+        assert_eq!(ArchiveIndex::from_json(&idx.to_json()), Some(idx));
+    }
+}
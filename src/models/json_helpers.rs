@@ -38,3 +38,113 @@ impl JsonHelper {
         }
     }
 }
+
+/// Finding a member of a json object in its text, without parsing the rest
+impl JsonHelper {
+    /// The byte range, within `text`, of the value of the top level member `key` of the json
+    /// object in `text`. Only the structure (strings, brackets and commas) is looked at, not
+    /// whether the values themselves are valid json. `None` when `text` isn't an object, or has
+    /// no such member.
+    pub fn member_span(text: &str, key: &str) -> Option<(usize, usize)> {
+        let bytes: &[u8] = text.as_bytes();
+        let mut ix: usize = JsonHelper::skip_space(bytes, 0);
+
+        if bytes.get(ix) != Some(&b'{') { return None }
+        ix = JsonHelper::skip_space(bytes, ix + 1);
+        if bytes.get(ix) == Some(&b'}') { return None }
+
+        loop {
+            if bytes.get(ix) != Some(&b'"') { return None }
+            let key_end: usize = JsonHelper::skip_string(bytes, ix)?;
+            /* Keys with escapes in them never match, which is fine for the keys gar looks for */
+            let found: bool = &text[ix + 1..key_end - 1] == key;
+
+            ix = JsonHelper::skip_space(bytes, key_end);
+            if bytes.get(ix) != Some(&b':') { return None }
+
+            let start: usize = JsonHelper::skip_space(bytes, ix + 1);
+            let end: usize = JsonHelper::skip_value(bytes, start)?;
+            if found { return Some((start, end)) }
+
+            ix = JsonHelper::skip_space(bytes, end);
+            match bytes.get(ix) {
+                Some(&b',') => ix = JsonHelper::skip_space(bytes, ix + 1),
+                _ => return None,
+            }
+        }
+    }
+
+    fn skip_space(bytes: &[u8], mut ix: usize) -> usize {
+        while ix < bytes.len() && (bytes[ix] as char).is_ascii_whitespace() {
+            ix += 1;
+        }
+        ix
+    }
+
+    /// Past the end of the string that starts (with its quote) at `ix`
+    fn skip_string(bytes: &[u8], mut ix: usize) -> Option<usize> {
+        ix += 1;
+        while ix < bytes.len() {
+            match bytes[ix] {
+                b'\\' => ix += 2,
+                b'"' => return Some(ix + 1),
+                _ => ix += 1,
+            }
+        }
+        None
+    }
+
+    /// Past the end of the value that starts at `ix`
+    fn skip_value(bytes: &[u8], mut ix: usize) -> Option<usize> {
+        match *bytes.get(ix)? {
+            b'"' => JsonHelper::skip_string(bytes, ix),
+            b'{' | b'[' => {
+                let mut depth: usize = 0;
+                while ix < bytes.len() {
+                    match bytes[ix] {
+                        b'"' => { ix = JsonHelper::skip_string(bytes, ix)?; continue },
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => {
+                            depth -= 1;
+                            if depth == 0 { return Some(ix + 1) }
+                        },
+                        _ => {},
+                    }
+                    ix += 1;
+                }
+                None
+            },
+            /* Numbers, true, false and null */
+            _ => {
+                let start: usize = ix;
+                while ix < bytes.len() && !b",}] \t\r\n".contains(&bytes[ix]) {
+                    ix += 1;
+                }
+                if ix == start { None } else { Some(ix) }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use models::json_helpers::JsonHelper;
+
+    fn member<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+        JsonHelper::member_span(text, key).map(|(start, end)| &text[start..end])
+    }
+
+    #[test]
+    fn test_member_span() -> () {
+        let text = r#"{"type":"PushEvent", "a\"payload":1,
+                       "payload" : {"commits":[{"message":"}]\"{"}],"size":1},"public":true}"#;
+
+        assert_eq!(member(text, "payload"), Some(r#"{"commits":[{"message":"}]\"{"}],"size":1}"#));
+        assert_eq!(member(text, "type"), Some(r#""PushEvent""#));
+        assert_eq!(member(text, "public"), Some("true"));
+        assert_eq!(member(text, "size"), None);
+        assert_eq!(member("[1]", "payload"), None);
+        assert_eq!(member("{}", "payload"), None);
+        assert_eq!(member(r#"{"payload":{"a":1"#, "payload"), None);
+    }
+}
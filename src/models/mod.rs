@@ -3,7 +3,14 @@ pub mod event;
 pub mod owner;
 pub mod reader;
 pub mod constraint;
+pub mod expr;
 pub mod event_type;
+pub mod verify;
+pub mod date_range;
+pub mod cache;
+pub mod index;
+pub mod compact;
+pub mod export;
 mod json_helpers;
 
 pub mod payloads;
@@ -1,3 +1,9 @@
+use std::io;
+use std::io::{Read, Write};
+
+use error::Result;
+use models::compact::{Compact, Decoder, Encoder};
+
 #[derive(Debug)]
 pub struct Owner {
     gh_id: u64,
@@ -19,7 +25,23 @@ impl Owner {
     }
 
     pub fn get_nick(&self) -> &String {
-        &self.email
+        &self.nick
+    }
+}
+
+impl Compact for Owner {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.u64(self.gh_id)?;
+        e.str(&self.nick)?;
+        e.str(&self.email)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<Owner> {
+        Ok(Owner {
+            gh_id: d.u64()?,
+            nick: d.string()?,
+            email: d.string()?,
+        })
     }
 }
 
@@ -1,4 +1,9 @@
+use std::io;
+use std::io::{Read, Write};
+
 use rustc_serialize::json::Json;
+use error::Result;
+use models::compact::{Compact, Decoder, Encoder};
 
 #[derive(Debug)]
 pub struct DeletePayload {
@@ -34,4 +39,26 @@ impl DeletePayload {
             ref_tag_type: ref_tag_type,
         })
     }
+
+    pub fn get_ref_tag(&self) -> &String {
+        &self.ref_tag
+    }
+
+    pub fn get_ref_tag_type(&self) -> &String {
+        &self.ref_tag_type
+    }
+}
+
+impl Compact for DeletePayload {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.str(&self.ref_tag)?;
+        e.str(&self.ref_tag_type)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<DeletePayload> {
+        Ok(DeletePayload {
+            ref_tag: d.string()?,
+            ref_tag_type: d.string()?,
+        })
+    }
 }
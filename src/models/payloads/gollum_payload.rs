@@ -1,5 +1,10 @@
+use std::io;
+use std::io::{Read, Write};
+
 use rustc_serialize::json::Json;
 use models::payloads::page_element::PageElement;
+use error::Result;
+use models::compact::{Compact, Decoder, Encoder};
 
 #[derive(Debug)]
 pub struct GollumPayload {
@@ -39,4 +44,18 @@ impl GollumPayload {
             pages: pes_vec,
         })
     }
+
+    pub fn get_pages(&self) -> &Vec<PageElement> {
+        &self.pages
+    }
+}
+
+impl Compact for GollumPayload {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.list(&self.pages)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<GollumPayload> {
+        Ok(GollumPayload { pages: d.list()? })
+    }
 }
@@ -1,5 +1,10 @@
+use std::io;
+use std::io::{Read, Write};
+
 use rustc_serialize::json::Json;
 use models::json_helpers::JsonHelper;
+use error::Result;
+use models::compact::{Compact, Decoder, Encoder};
 
 #[derive(Debug)]
 pub struct IssueCommentPayload {
@@ -14,12 +19,42 @@ impl IssueCommentPayload {
 
         let obj = json.unwrap().as_object().unwrap();
 
-        let cid: u64 = JsonHelper::number_or_zero(obj.get("comment_id"));
-        let iid: u64 = JsonHelper::number_or_zero(obj.get("issue_id"));
+        let mut cid: u64 = JsonHelper::number_or_zero(obj.get("comment_id"));
+        let mut iid: u64 = JsonHelper::number_or_zero(obj.get("issue_id"));
+
+        /* Events API: comment and issue are embedded objects */
+        if let Some(Json::Object(c)) = obj.get("comment") {
+            cid = JsonHelper::number_or_zero(c.get("id"));
+        }
+        if let Some(Json::Object(i)) = obj.get("issue") {
+            iid = JsonHelper::number_or_zero(i.get("id"));
+        }
 
         Some(IssueCommentPayload {
             comment_id: cid,
             issue_id: iid,
         })
     }
+
+    pub fn get_comment_id(&self) -> u64 {
+        self.comment_id
+    }
+
+    pub fn get_issue_id(&self) -> u64 {
+        self.issue_id
+    }
+}
+
+impl Compact for IssueCommentPayload {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.u64(self.comment_id)?;
+        e.u64(self.issue_id)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<IssueCommentPayload> {
+        Ok(IssueCommentPayload {
+            comment_id: d.u64()?,
+            issue_id: d.u64()?,
+        })
+    }
 }
@@ -1,4 +1,10 @@
+use std::io;
+use std::io::{Read, Write};
+
 use rustc_serialize::json::*;
+use models::json_helpers::JsonHelper;
+use error::Result;
+use models::compact::{Compact, Decoder, Encoder};
 
 #[derive(Debug)]
 pub struct IssuePayload {
@@ -28,6 +34,8 @@ impl IssuePayload {
         let issue: u64 = match json.get("issue") {
             Some(v) => match *v {
                 Json::U64(v) => v,
+                /* Events API: the whole issue object is embedded */
+                Json::Object(ref o) => JsonHelper::number_or_zero(o.get("id")),
                 _ => 0,
             },
             None => 0,
@@ -38,7 +46,10 @@ impl IssuePayload {
                 Json::U64(v) => v,
                 _ => 0,
             },
-            None => 0,
+            None => match json.get("issue") {
+                Some(Json::Object(o)) => JsonHelper::number_or_zero(o.get("number")),
+                _ => 0,
+            },
         };
 
         Some(
@@ -48,6 +59,18 @@ impl IssuePayload {
                 .number(number)
                 .finalize())
     }
+
+    pub fn get_action(&self) -> &String {
+        &self.action
+    }
+
+    pub fn get_issue(&self) -> u64 {
+        self.issue
+    }
+
+    pub fn get_number(&self) -> u64 {
+        self.number
+    }
 }
 
 pub struct IssuePayloadBuilder {
@@ -88,3 +111,19 @@ impl IssuePayloadBuilder {
         }
     }
 }
+
+impl Compact for IssuePayload {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.str(&self.action)?;
+        e.u64(self.issue)?;
+        e.u64(self.number)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<IssuePayload> {
+        Ok(IssuePayload {
+            action: d.string()?,
+            issue: d.u64()?,
+            number: d.u64()?,
+        })
+    }
+}
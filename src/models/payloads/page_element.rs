@@ -1,5 +1,10 @@
+use std::io;
+use std::io::{Read, Write};
+
 use rustc_serialize::json::Json;
 use models::json_helpers::JsonHelper;
+use error::Result;
+use models::compact::{Compact, Decoder, Encoder};
 
 /// This is one of the elements found within the GollumEvent payload
 #[derive(Debug)]
@@ -40,4 +45,44 @@ impl PageElement {
             summary: summary,
         })
     }
+
+    pub fn get_action(&self) -> &String {
+        &self.action
+    }
+
+    pub fn get_html_url(&self) -> &String {
+        &self.html_url
+    }
+
+    pub fn get_page_name(&self) -> &String {
+        &self.page_name
+    }
+
+    pub fn get_sha(&self) -> &String {
+        &self.sha
+    }
+
+    pub fn get_summary(&self) -> Option<&String> {
+        self.summary.as_ref()
+    }
+}
+
+impl Compact for PageElement {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.str(&self.action)?;
+        e.str(&self.html_url)?;
+        e.str(&self.page_name)?;
+        e.str(&self.sha)?;
+        e.option(&self.summary)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<PageElement> {
+        Ok(PageElement {
+            action: d.string()?,
+            html_url: d.string()?,
+            page_name: d.string()?,
+            sha: d.string()?,
+            summary: d.option()?,
+        })
+    }
 }
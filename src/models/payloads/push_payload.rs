@@ -1,8 +1,12 @@
-use regex::Regex;
+use std::io;
+use std::io::{Read, Write};
+
 use rustc_serialize::json::Json;
 
+use error::Result;
 use models::payloads::ShaElement;
 use models::json_helpers::JsonHelper;
+use models::compact::{Compact, Decoder, Encoder};
 
 #[derive(Debug)]
 pub struct PushPayload {
@@ -41,6 +45,15 @@ impl PushPayload {
             None => {},
         }
 
+        /* Events API pushes list their commits as objects under "commits" instead */
+        if let Some(Json::Array(a)) = obj.get("commits") {
+            for commit in a.iter() {
+                if let Some(v) = ShaElement::from_commit_json(commit) {
+                    shas.push(v);
+                }
+            }
+        }
+
         Some(PushPayload {
             head: head,
             refs: refs,
@@ -49,15 +62,37 @@ impl PushPayload {
         })
     }
 
-    /// Given some text, check to see if any of the sha commits contain that text
-    pub fn sha_elements_contain_text_of(&self, text: &str) -> bool {
-        let re_txt: String = format!("(?i){}", text);
-        let re: Regex = Regex::new(re_txt.as_ref()).unwrap();
+    pub fn get_head(&self) -> &String {
+        &self.head
+    }
 
-        self.shas
-            .iter()
-            .map(|e| re.is_match(e.get_comment().as_ref()))
-            .fold(false, |e,sum| sum || e)
+    pub fn get_ref(&self) -> &String {
+        &self.refs
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_shas(&self) -> &Vec<ShaElement> {
+        &self.shas
     }
 }
 
+impl Compact for PushPayload {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.str(&self.head)?;
+        e.str(&self.refs)?;
+        e.u64(self.size)?;
+        e.list(&self.shas)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<PushPayload> {
+        Ok(PushPayload {
+            head: d.string()?,
+            refs: d.string()?,
+            size: d.u64()?,
+            shas: d.list()?,
+        })
+    }
+}
@@ -1,4 +1,10 @@
+use std::io;
+use std::io::{Read, Write};
+
 use rustc_serialize::json::*;
+use models::json_helpers::JsonHelper;
+use error::Result;
+use models::compact::{Compact, Decoder, Encoder};
 
 #[derive(Debug)]
 pub struct ShaElement {
@@ -47,6 +53,22 @@ impl ShaElement {
         })
     }
 
+    /// Events API flavour of a commit: `{"sha", "author": {"email", "name"}, "message",
+    /// "distinct"}`
+    pub fn from_commit_json(json: &Json) -> Option<ShaElement> {
+        let obj = json.as_object()?;
+
+        let author = obj.get("author").and_then(|v| v.as_object());
+
+        Some(ShaElement {
+            sha: JsonHelper::string_or_empty(obj.get("sha")),
+            email: JsonHelper::string_or_empty(author.and_then(|a| a.get("email"))),
+            comment: JsonHelper::string_or_empty(obj.get("message")),
+            author: JsonHelper::string_or_empty(author.and_then(|a| a.get("name"))),
+            distinct: JsonHelper::boolean_or_false(obj.get("distinct")),
+        })
+    }
+
     pub fn from_values(s: String, e: String, c: String, a: String,
                        d: bool) -> ShaElement {
         ShaElement {
@@ -61,5 +83,40 @@ impl ShaElement {
     pub fn get_comment(&self) -> &String {
         &self.comment
     }
+
+    pub fn get_sha(&self) -> &String {
+        &self.sha
+    }
+
+    pub fn get_email(&self) -> &String {
+        &self.email
+    }
+
+    pub fn get_author(&self) -> &String {
+        &self.author
+    }
+
+    pub fn is_distinct(&self) -> bool {
+        self.distinct
+    }
 }
 
+impl Compact for ShaElement {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.str(&self.sha)?;
+        e.str(&self.email)?;
+        e.str(&self.comment)?;
+        e.str(&self.author)?;
+        e.bool(self.distinct)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<ShaElement> {
+        Ok(ShaElement {
+            sha: d.string()?,
+            email: d.string()?,
+            comment: d.string()?,
+            author: d.string()?,
+            distinct: d.bool()?,
+        })
+    }
+}
@@ -1,5 +1,10 @@
+use std::io;
+use std::io::{Read, Write};
+
 use models::json_helpers::JsonHelper;
 use rustc_serialize::json::*;
+use error::Result;
+use models::compact::{Compact, Decoder, Encoder};
 
 #[derive(Debug)]
 pub struct WatchPayload {
@@ -22,4 +27,18 @@ impl WatchPayload {
 
         Some(WatchPayload { action: action } )
     }
+
+    pub fn get_action(&self) -> &String {
+        &self.action
+    }
+}
+
+impl Compact for WatchPayload {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>) -> io::Result<()> {
+        e.str(&self.action)
+    }
+
+    fn decode<R: Read>(d: &mut Decoder<R>) -> Result<WatchPayload> {
+        Ok(WatchPayload { action: d.string()? })
+    }
 }
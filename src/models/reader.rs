@@ -1,55 +1,195 @@
 use std::io;
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, Read};
 use std::path::PathBuf;
 use std::fs::File;
-use flate2::read::GzDecoder;
+use std::sync::atomic::{AtomicU64, Ordering};
+use flate2::read::MultiGzDecoder;
+use zstd;
 
-/// Given a path to the json.gz file,
-pub fn deflate_to_contents(p: PathBuf) -> Option<String> {
-    let mut stderr = io::stderr();
-    let pp: PathBuf = p.clone();
-    let ppstring: &str = pp.to_str().unwrap_or("[uncapable of unwraping]");
+use error::{GarError, Result};
 
-    let mut f: File = match File::open(p) {
-        Ok(f) => f,
-        Err(..) => return None,
-    };
+/// How an archive is stored. githubarchive serves gzip, but archives may also be recompressed
+/// with zstd, or kept as plain newline delimited json.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    /// One or more concatenated gzip members
+    Gzip,
+    /// One or more zstd frames
+    Zstd,
+    /// Not compressed at all
+    Plain,
+}
 
-    let mut bytes: Vec<u8> = Vec::new();
+impl Compression {
+    /// Tell how `bytes`, the start of an archive, are compressed, from their magic number
+    pub fn sniff(bytes: &[u8]) -> Compression {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        }
+        else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        }
+        else {
+            Compression::Plain
+        }
+    }
 
-    match f.read_to_end(&mut bytes) {
-        Ok(..) => {},
-        Err(e) => {
-            writeln!(&mut stderr, "Problem loading file: {}", e).unwrap();
-            return None;
-        },
+    /// How an archive called `name` should be compressed, going by its extension: `.gz` and `.zst`
+    /// say, anything else could be plain json
+    pub fn of_name(name: &str) -> Option<Compression> {
+        if name.ends_with(".gz") {
+            Some(Compression::Gzip)
+        }
+        else if name.ends_with(".zst") {
+            Some(Compression::Zstd)
+        }
+        else {
+            None
+        }
     }
+}
 
-    let bb: &[u8] = bytes.as_ref();
-    let mut d = GzDecoder::new(bb);
-    let mut decomp: String = String::new();
+/// How many bytes have come out of `Counted` readers so far, in the whole process
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+
+/// How many bytes of archives have been read so far, once decompressed (see `decompress`), in
+/// the whole process. Progress reports use it to tell how fast queries go.
+pub fn bytes_read() -> u64 {
+    BYTES_READ.load(Ordering::Relaxed)
+}
 
-    match d.read_to_string(&mut decomp) {
-        Err(e) => {
-            writeln!(&mut stderr,
-                     "Problem reading archive to string from error {}; archive was {}",
-                     e, ppstring).unwrap();
-            return None;
-        },
-        Ok(..) => {},
+/// A reader that adds what it reads to `bytes_read`
+pub struct Counted<R>(pub R);
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n: usize = self.0.read(buf)?;
+        BYTES_READ.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
     }
+}
 
-    Some(decomp)
+/// The decompressed contents of `r`, whatever `Compression` it turns out to use. Reading the
+/// stream to its end checks the trailers of gzip members and the checksums of zstd frames, so
+/// truncated and corrupt archives end with an error.
+pub fn decompress<'a, R: Read + 'a>(r: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let mut raw = BufReader::new(r);
+    let compression = Compression::sniff(raw.fill_buf()?);
+
+    Ok(match compression {
+        Compression::Gzip => Box::new(BufReader::new(Counted(MultiGzDecoder::new(raw)))),
+        Compression::Zstd => Box::new(BufReader::new(Counted(zstd::Decoder::with_buffer(raw)?))),
+        Compression::Plain => Box::new(BufReader::new(Counted(raw))),
+    })
 }
 
-/// Deflate and read each line. Each line in the data file corresponds into an entry
-pub fn lines_of(p: PathBuf) -> Vec<String> {
-    let data: String = match deflate_to_contents(p) {
-        Some(v) => v,
-        None => "".into(),
-    };
+/// The lines of an archive, decompressed a buffer at a time, so that only the current line is
+/// ever held in memory. Decompression errors (a truncated or corrupt archive) come out as one last
+/// `Err`, after the lines that could be read.
+pub struct ArchiveLines<'a> {
+    reader: Option<Box<dyn BufRead + 'a>>,
+    buf: Vec<u8>,
+    origin: String,
+}
 
-    data.lines()
-        .map(|e| e.into())
-        .collect()
+impl<'a> ArchiveLines<'a> {
+    /// `origin` says where the archive came from, for error messages. Fails when the start of
+    /// the archive can't be read.
+    pub fn new<R: Read + 'a>(r: R, origin: &str) -> Result<ArchiveLines<'a>> {
+        match decompress(r) {
+            Ok(reader) => Ok(ArchiveLines {
+                reader: Some(reader),
+                buf: Vec::new(),
+                origin: origin.into(),
+            }),
+            Err(e) => Err(GarError::Parse(format!("could not decompress archive {}: {}", origin, e))),
+        }
+    }
+}
+
+impl<'a> Iterator for ArchiveLines<'a> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
+        loop {
+            self.buf.clear();
+            let read = self.reader.as_mut()?.read_until(b'\n', &mut self.buf);
+
+            match read {
+                Ok(0) => {
+                    self.reader = None;
+                    return None;
+                },
+                Ok(..) => {},
+                Err(e) => {
+                    self.reader = None;
+                    return Some(Err(GarError::Parse(
+                        format!("could not decompress archive {}: {}", self.origin, e))));
+                },
+            }
+
+            let line = String::from_utf8_lossy(&self.buf);
+            let line = line.trim();
+            if !line.is_empty() {
+                return Some(Ok(line.into()));
+            }
+        }
+    }
+}
+
+/// The lines of the archive at `p`, as they are read
+pub fn archive_lines_of(p: PathBuf) -> Result<ArchiveLines<'static>> {
+    let origin: String = p.to_str().unwrap_or("[uncapable of unwraping]").into();
+    ArchiveLines::new(File::open(p)?, &origin)
+}
+
+#[cfg(test)]
+mod test {
+    use flate2::write::GzEncoder;
+    use flate2::Compression as GzCompression;
+    use std::io::Write;
+    use zstd;
+
+    use models::reader::*;
+
+    fn gzip(s: &str) -> Vec<u8> {
+        let mut enc = GzEncoder::new(Vec::new(), GzCompression::default());
+        enc.write_all(s.as_bytes()).unwrap();
+        enc.finish().unwrap()
+    }
+
+    fn lines(bytes: &[u8]) -> Vec<String> {
+        ArchiveLines::new(bytes, "test").unwrap().map(|l| l.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_sniff() -> () {
+        assert_eq!(Compression::sniff(&gzip("a")), Compression::Gzip);
+        assert_eq!(Compression::sniff(&zstd::encode_all("a".as_bytes(), 0).unwrap()), Compression::Zstd);
+        assert_eq!(Compression::sniff(b"{\"a\":1}"), Compression::Plain);
+        assert_eq!(Compression::sniff(b""), Compression::Plain);
+
+        assert_eq!(Compression::of_name("2015-01-01-0.json.gz"), Some(Compression::Gzip));
+        assert_eq!(Compression::of_name("2015-01-01-0.ndjson.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::of_name("2015-01-01-0.json"), None);
+    }
+
+    #[test]
+    fn test_archive_lines() -> () {
+        /* Several gzip members one after the other, as made by `cat a.gz b.gz` */
+        let mut multi: Vec<u8> = gzip("one\n");
+        multi.extend(gzip("two\n\nthree\n"));
+        assert_eq!(lines(&multi), vec!["one", "two", "three"]);
+
+        let zst: Vec<u8> = zstd::encode_all("one\ntwo\n".as_bytes(), 0).unwrap();
+        assert_eq!(lines(&zst), vec!["one", "two"]);
+
+        assert_eq!(lines(b"one\r\ntwo"), vec!["one", "two"]);
+
+        /* Truncated archives end with an error */
+        let last = ArchiveLines::new(&multi[..multi.len() - 4], "test").unwrap().last().unwrap();
+        assert!(last.is_err());
+        let last = ArchiveLines::new(&zst[..zst.len() - 4], "test").unwrap().last().unwrap();
+        assert!(last.is_err());
+    }
 }
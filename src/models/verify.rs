@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use rustc_serialize::json::Json;
+
+use models::event::Event;
+use models::reader;
+use models::reader::Compression;
+
+/// Outcome of checking one cached archive.
+#[derive(Debug)]
+pub struct Verification {
+    pub path: PathBuf,
+    /// Lines that parsed into an `Event`
+    pub events: u64,
+    /// Non empty lines that are not valid json, or not an event we understand
+    pub unparseable: u64,
+    /// Why the archive is unusable, if it is
+    pub problem: Option<String>,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+/// Decompress the whole archive at `p`, and try to parse each line of it. Reading the stream to
+/// its end makes the decoder check the gzip trailers (or zstd checksums), so truncated files and
+/// corrupted data both show up as a problem here. So does a `.gz` or `.zst` archive that isn't
+/// compressed that way (such as an error page saved in its place). Plain json archives can only be
+/// checked line by line.
+pub fn verify(p: &Path) -> Verification {
+    let mut v = Verification {
+        path: p.to_path_buf(),
+        events: 0,
+        unparseable: 0,
+        problem: None,
+    };
+
+    let mut f: BufReader<File> = match File::open(p) {
+        Ok(f) => BufReader::new(f),
+        Err(e) => {
+            v.problem = Some(format!("could not open: {}", e));
+            return v;
+        },
+    };
+
+    let expected = p.file_name().and_then(|f| f.to_str()).and_then(Compression::of_name);
+    match (expected, f.fill_buf().map(Compression::sniff)) {
+        (Some(expected), Ok(found)) if expected != found => {
+            v.problem = Some(format!("not {:?} compressed", expected).to_lowercase());
+            return v;
+        },
+        (_, Err(e)) => {
+            v.problem = Some(format!("could not read: {}", e));
+            return v;
+        },
+        _ => {},
+    }
+
+    let mut reader = match reader::decompress(f) {
+        Ok(r) => r,
+        Err(e) => {
+            v.problem = Some(format!("could not read: {}", e));
+            return v;
+        },
+    };
+    let mut line: Vec<u8> = Vec::new();
+
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(..) => {},
+            Err(e) => {
+                v.problem = Some(match e.kind() {
+                    ErrorKind::UnexpectedEof => "truncated".into(),
+                    _ => format!("corrupt ({})", e),
+                });
+                return v;
+            },
+        }
+
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim();
+        if text.is_empty() { continue }
+
+        match Json::from_str(text) {
+            Ok(json) if Event::from_json(Some(&json)).is_some() => v.events += 1,
+            _ => v.unparseable += 1,
+        }
+    }
+
+    if v.events == 0 {
+        v.problem = Some("no events".into());
+    }
+
+    v
+}
+
+#[cfg(test)]
+mod test {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    use models::verify::*;
+
+    const EVENT: &str = r#"{"type":"ForkEvent","repository":{"id":1,"name":"gar","language":"Rust"}}"#;
+
+    fn gzip(s: &str) -> Vec<u8> {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(s.as_bytes()).unwrap();
+        enc.finish().unwrap()
+    }
+
+    /// Write `bytes` to a file called `name` in `dir`
+    fn archive(dir: &Path, name: &str, bytes: &[u8]) -> PathBuf {
+        let p: PathBuf = dir.join(name);
+        fs::write(&p, bytes).unwrap();
+        p
+    }
+
+    #[test]
+    fn test_verify() -> () {
+        let dir: PathBuf = env::temp_dir().join(format!("gar-verify-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let good: Vec<u8> = gzip(&format!("{}\n{}\n", EVENT, EVENT));
+
+        let v = verify(&archive(&dir, "2015-01-01-0.json.gz", &good));
+        assert_eq!((v.events, v.unparseable, v.problem), (2, 0, None));
+
+        let v = verify(&archive(&dir, "2015-01-01-1.json.gz", &good[..good.len() - 4]));
+        assert_eq!((v.events, v.unparseable, v.problem), (2, 0, Some("truncated".into())));
+
+        let v = verify(&archive(&dir, "2015-01-01-2.json.gz", &gzip(&format!("{}\nnot json\n", EVENT))));
+        assert_eq!((v.events, v.unparseable, v.problem), (1, 1, None));
+
+        let v = verify(&archive(&dir, "2015-01-01-3.json.gz", &gzip("\n")));
+        assert_eq!((v.events, v.unparseable, v.problem), (0, 0, Some("no events".into())));
+
+        let v = verify(&archive(&dir, "2015-01-01-4.json.gz", &gzip("<html>\nnot json\n")));
+        assert_eq!((v.events, v.unparseable, v.problem), (0, 2, Some("no events".into())));
+
+        /* An error page saved under an archive's name */
+        let v = verify(&archive(&dir, "2015-01-01-5.json.gz", b"<html>\n</html>\n"));
+        assert_eq!(v.problem, Some("not gzip compressed".into()));
+        let v = verify(&archive(&dir, "2015-01-01-6.json", format!("{}\n", EVENT).as_bytes()));
+        assert_eq!((v.events, v.unparseable, v.problem), (1, 0, None));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
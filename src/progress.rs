@@ -0,0 +1,270 @@
+use std::io;
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use rustc_serialize::json::{Json, Object};
+
+use error::{GarError, Result};
+
+/// How often the progress line is redrawn
+const TEXT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often a json progress report is written
+const JSON_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How progress is reported on stderr, as chosen with `--progress`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressMode {
+    Off,
+    /// A line that keeps being redrawn, for people to look at
+    Text,
+    /// One json object per line, for programs to read
+    Json,
+}
+
+impl ProgressMode {
+    /// The value of a `--progress` flag: "text", "json" or "off"
+    pub fn parse(s: &str) -> Result<ProgressMode> {
+        match s {
+            "text" => Ok(ProgressMode::Text),
+            "json" => Ok(ProgressMode::Json),
+            "off" => Ok(ProgressMode::Off),
+            _ => Err(GarError::Parse(format!("progress can be text, json or off, not {:?}", s))),
+        }
+    }
+}
+
+/// Where a long fetch or query is at: files done out of how many, bytes gone through, and for
+/// queries, events seen and matches found. Whoever does the work updates the public fields, and
+/// calls `tick` often; the report itself only goes out every so often.
+///
+/// Text progress is only shown when stderr is a terminal. Since it keeps redrawing the same line,
+/// `clear` should be called before printing anything else to the terminal.
+pub struct Progress {
+    mode: ProgressMode,
+    /// What is going on ("fetch", "query"), to start each report with
+    what: &'static str,
+    started: Instant,
+    shown_at: Option<Instant>,
+    /// True while a text progress line is on the screen
+    on_screen: bool,
+    pub files_total: usize,
+    pub files_done: usize,
+    pub bytes: u64,
+    /// Events gone through, for queries
+    pub events: Option<u64>,
+    /// Matches found so far, for queries
+    pub matches: Option<u64>,
+}
+
+impl Progress {
+    pub fn new(what: &'static str, mode: ProgressMode, files_total: usize) -> Progress {
+        let mode: ProgressMode = match mode {
+            ProgressMode::Text if !io::stderr().is_terminal() => ProgressMode::Off,
+            m => m,
+        };
+
+        Progress {
+            mode,
+            what,
+            started: Instant::now(),
+            shown_at: None,
+            on_screen: false,
+            files_total,
+            files_done: 0,
+            bytes: 0,
+            events: None,
+            matches: None,
+        }
+    }
+
+    /// Report progress, if it is time to
+    pub fn tick(&mut self) {
+        let interval: Duration = match self.mode {
+            ProgressMode::Off => return,
+            ProgressMode::Text => TEXT_INTERVAL,
+            ProgressMode::Json => JSON_INTERVAL,
+        };
+
+        if self.shown_at.is_none_or(|t| t.elapsed() >= interval) {
+            self.show(false);
+        }
+    }
+
+    /// Take the text progress line off the screen, until the next `tick`
+    pub fn clear(&mut self) {
+        if self.on_screen {
+            eprint!("\r\x1b[K");
+            self.on_screen = false;
+        }
+    }
+
+    /// Report progress one last time, now that the work is over
+    pub fn finish(&mut self) {
+        match self.mode {
+            ProgressMode::Off => {},
+            ProgressMode::Text => {
+                self.show(true);
+                eprintln!();
+                self.on_screen = false;
+            },
+            ProgressMode::Json => self.show(true),
+        }
+    }
+
+    fn show(&mut self, done: bool) {
+        /* Results printed so far should come out before the progress line */
+        let _ = io::stdout().flush();
+
+        match self.mode {
+            ProgressMode::Off => return,
+            ProgressMode::Text => {
+                eprint!("\r\x1b[K{}", self.to_text());
+                self.on_screen = true;
+            },
+            ProgressMode::Json => eprintln!("{}", self.to_json(done)),
+        }
+
+        let _ = io::stderr().flush();
+        self.shown_at = Some(Instant::now());
+    }
+
+    /// Events gone through per second, on average since the start
+    fn events_per_sec(&self) -> Option<u64> {
+        let secs: f64 = self.started.elapsed().as_secs_f64();
+        self.events.map(|e| if secs > 0.0 { (e as f64 / secs) as u64 } else { 0 })
+    }
+
+    /// Time left, going by how long the files done so far took
+    fn eta(&self) -> Option<Duration> {
+        if self.files_done == 0 { return None }
+
+        let left: usize = self.files_total.saturating_sub(self.files_done);
+        Some(self.started.elapsed().mul_f64(left as f64 / self.files_done as f64))
+    }
+
+    /// Something like "query: 3/10 files, 1.2 GB, 45000 events/s, 12 matches, ETA 1m20s"
+    fn to_text(&self) -> String {
+        let mut parts: Vec<String> = vec![
+            format!("{}/{} files", self.files_done, self.files_total),
+            human_bytes(self.bytes),
+        ];
+
+        if let Some(rate) = self.events_per_sec() {
+            parts.push(format!("{} events/s", rate));
+        }
+        if let Some(matches) = self.matches {
+            parts.push(format!("{} matches", matches));
+        }
+        if let Some(eta) = self.eta() {
+            parts.push(format!("ETA {}", human_duration(eta)));
+        }
+
+        format!("{}: {}", self.what, parts.join(", "))
+    }
+
+    /// One report for `--progress=json`. Counts that don't apply (say, matches while fetching)
+    /// are left out, and `eta_secs` is null until a file is done.
+    fn to_json(&self, done: bool) -> Json {
+        let mut obj: Object = Object::new();
+
+        obj.insert("command".into(), Json::String(self.what.into()));
+        obj.insert("files_done".into(), Json::U64(self.files_done as u64));
+        obj.insert("files_total".into(), Json::U64(self.files_total as u64));
+        obj.insert("bytes".into(), Json::U64(self.bytes));
+        if let Some(events) = self.events {
+            obj.insert("events".into(), Json::U64(events));
+        }
+        if let Some(rate) = self.events_per_sec() {
+            obj.insert("events_per_sec".into(), Json::U64(rate));
+        }
+        if let Some(matches) = self.matches {
+            obj.insert("matches".into(), Json::U64(matches));
+        }
+        obj.insert("elapsed_secs".into(), Json::F64(self.started.elapsed().as_secs_f64()));
+        obj.insert("eta_secs".into(), self.eta().map_or(Json::Null, |d| Json::F64(d.as_secs_f64())));
+        obj.insert("done".into(), Json::Boolean(done));
+
+        Json::Object(obj)
+    }
+}
+
+/// Bytes in the largest unit that keeps the number above one, like "1.2 GB"
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size: f64 = bytes as f64;
+    let mut unit: usize = 0;
+
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", size, units[unit]) }
+}
+
+/// Like "1h02m", "3m20s" or "45s"
+fn human_duration(d: Duration) -> String {
+    let secs: u64 = d.as_secs();
+
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+    else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+    else {
+        format!("{}s", secs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use progress::*;
+
+    #[test]
+    fn test_parse_mode() -> () {
+        assert_eq!(ProgressMode::parse("text").unwrap(), ProgressMode::Text);
+        assert_eq!(ProgressMode::parse("json").unwrap(), ProgressMode::Json);
+        assert_eq!(ProgressMode::parse("off").unwrap(), ProgressMode::Off);
+        assert!(ProgressMode::parse("loud").is_err());
+    }
+
+    #[test]
+    fn test_reports() -> () {
+        let mut p = Progress::new("query", ProgressMode::Json, 4);
+        p.files_done = 1;
+        p.bytes = 3 * 1024 * 1024;
+        p.events = Some(10);
+        p.matches = Some(2);
+
+        let json = p.to_json(false);
+        let obj = json.as_object().unwrap();
+        assert_eq!(obj["command"].as_string(), Some("query"));
+        assert_eq!(obj["files_total"].as_u64(), Some(4));
+        assert_eq!(obj["matches"].as_u64(), Some(2));
+        assert!(obj["eta_secs"].is_number());
+        assert_eq!(obj["done"].as_boolean(), Some(false));
+
+        let text = p.to_text();
+        assert!(text.starts_with("query: 1/4 files, 3.0 MB, "), "{}", text);
+        assert!(text.contains(", 2 matches, ETA "), "{}", text);
+
+        /* Fetches have no events nor matches to speak of */
+        let p = Progress::new("fetch", ProgressMode::Json, 4);
+        assert!(p.to_json(false).as_object().unwrap().get("matches").is_none());
+        assert!(p.to_json(false).as_object().unwrap()["eta_secs"].is_null());
+        assert_eq!(p.to_text(), "fetch: 0/4 files, 0 B");
+    }
+
+    #[test]
+    fn test_human_units() -> () {
+        assert_eq!(human_bytes(512), "512 B");
+        assert_eq!(human_bytes(1536), "1.5 KB");
+        assert_eq!(human_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(human_duration(Duration::from_secs(200)), "3m20s");
+        assert_eq!(human_duration(Duration::from_secs(3720)), "1h02m");
+    }
+}